@@ -0,0 +1,301 @@
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task}, utils::synccell::SyncCell};
+use futures_lite::future;
+
+use crate::util::{AppState, GameMode, Score, FruitTable, GameRng, MIN_SPEED, FIXED_DT, TEXT_COLOR};
+use crate::settings::Settings;
+use crate::game::{GameSet, Positions, Controls, ActiveFruit, FruitQueue, HoldSlot, Chain, GameStats};
+use crate::save::{snapshot, BoardFruits};
+use crate::sim::{Lookahead, Sandbox};
+
+// drop positions tried across the cup
+const CANDIDATES: usize = 33;
+// don't wait longer than this for the board to settle before the next drop
+const MAX_SETTLE_TICKS: u32 = 120;
+// seconds on the menu or game over screen before the next demo game starts
+const RESTART_DELAY: f32 = 3.0;
+
+pub struct AutoPlayerPlugin;
+
+impl Plugin for AutoPlayerPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<AutoPlayer>()
+      .add_systems(Startup, spawn_label)
+      .add_systems(Update, toggle_autoplayer.run_if(in_state(AppState::InGame)))
+      .add_systems(Update, update_label.run_if(resource_changed::<AutoPlayer>()))
+      .add_systems(OnEnter(AppState::InGame), reset_autoplayer)
+      .add_systems(FixedUpdate, drive_controls
+        .in_set(GameSet::Control)
+        .run_if(autoplayer_enabled)
+        .run_if(in_state(AppState::InGame)))
+      // attract mode, keeps playing game after game
+      .add_systems(OnEnter(AppState::Menu), reset_restart_timer)
+      .add_systems(OnEnter(AppState::GameOver), reset_restart_timer)
+      .add_systems(Update, restart_game
+        .run_if(autoplayer_enabled)
+        .run_if(restarts_in_place)
+        .run_if(in_state(AppState::Menu).or_else(in_state(AppState::GameOver))));
+  }
+}
+
+// -- RESOURCES --
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Strategy {
+  // rates each spot by where the fruit would land
+  #[default]
+  Heuristic,
+  // plays spots out in headless games, see sim::Lookahead
+  Lookahead,
+}
+
+// takes over Controls while enabled, B toggles it in game
+#[derive(Resource)]
+pub struct AutoPlayer {
+  pub enabled: bool,
+  pub strategy: Strategy,
+  target: Option<f32>,
+  // lookahead runs off the main thread, the bot waits for it. The task takes the
+  // sandbox along and hands it back with the drop, so it's only built once
+  search: Option<Task<(f32, Sandbox)>>,
+  sandbox: Option<SyncCell<Sandbox>>,
+  wait: u32,
+  settle_ticks: u32,
+  restart: Timer,
+}
+
+impl AutoPlayer {
+  pub fn new(enabled: bool) -> Self {
+    AutoPlayer {
+      enabled,
+      strategy: Strategy::Heuristic,
+      target: None,
+      search: None,
+      sandbox: None,
+      wait: 0,
+      settle_ticks: 0,
+      restart: Timer::from_seconds(RESTART_DELAY, TimerMode::Once),
+    }
+  }
+
+  pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+    self.strategy = strategy;
+    self
+  }
+}
+
+impl Default for AutoPlayer {
+  fn default() -> Self {
+    AutoPlayer::new(false)
+  }
+}
+
+// -- COMPONENTS --
+#[derive(Component)]
+struct AutoPlayerLabel;
+
+// -- SYSTEMS --
+fn spawn_label(mut commands: Commands, autoplayer: Res<AutoPlayer>) {
+  commands.spawn((
+    AutoPlayerLabel,
+    Text2dBundle {
+      text: Text::from_section(
+        "autoplay (B to stop)",
+        TextStyle {
+          font_size: 22.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 295.0, 10.0)),
+      visibility: label_visibility(&autoplayer),
+      ..default()
+    },
+  ));
+}
+
+fn update_label(
+  autoplayer: Res<AutoPlayer>,
+  mut label: Query<&mut Visibility, With<AutoPlayerLabel>>,
+) {
+  for mut visibility in label.iter_mut() {
+    *visibility = label_visibility(&autoplayer);
+  }
+}
+
+fn toggle_autoplayer(
+  keys: Res<Input<KeyCode>>,
+  mut autoplayer: ResMut<AutoPlayer>,
+) {
+  if keys.just_pressed(KeyCode::B) {
+    autoplayer.enabled = !autoplayer.enabled;
+    autoplayer.target = None;
+    autoplayer.search = None;
+  }
+}
+
+fn reset_autoplayer(mut autoplayer: ResMut<AutoPlayer>) {
+  autoplayer.target = None;
+  autoplayer.search = None;
+  autoplayer.wait = 0;
+  autoplayer.settle_ticks = 0;
+}
+
+fn reset_restart_timer(mut autoplayer: ResMut<AutoPlayer>) {
+  autoplayer.restart.reset();
+}
+
+fn restart_game(
+  time: Res<Time>,
+  mut autoplayer: ResMut<AutoPlayer>,
+  mut next_state: ResMut<NextState<AppState>>,
+) {
+  if autoplayer.restart.tick(time.delta()).just_finished() {
+    next_state.set(AppState::InGame);
+  }
+}
+
+fn drive_controls(
+  mut autoplayer: ResMut<AutoPlayer>,
+  positions: Res<Positions>,
+  table: Res<FruitTable>,
+  settings: Res<Settings>,
+  mut controls: Query<&mut Controls>,
+  active_fruit: Query<(&ActiveFruit, &Transform)>,
+  fruits: BoardFruits,
+  queue: Res<FruitQueue>,
+  hold: Res<HoldSlot>,
+  chain: Res<Chain>,
+  stats: Res<GameStats>,
+  score: Res<Score>,
+  rng: Res<GameRng>,
+) {
+  let Ok(mut controls) = controls.get_single_mut() else {
+    return;
+  };
+  controls.move_dir = 0.0;
+  controls.drop = false;
+  controls.hold = false;
+
+  // wait out the drop cooldown, then until nothing is moving
+  autoplayer.settle_ticks += 1;
+  if autoplayer.wait > 0 {
+    autoplayer.wait -= 1;
+    return;
+  }
+  let settled = fruits.iter().all(|(_, _, v, _)| v.linvel.length() < MIN_SPEED);
+  if !settled && autoplayer.settle_ticks < MAX_SETTLE_TICKS {
+    return;
+  }
+  let Ok((active, transform)) = active_fruit.get_single() else {
+    return;
+  };
+
+  if autoplayer.target.is_none() {
+    match autoplayer.strategy {
+      Strategy::Heuristic => {
+        let board: Vec<(Vec2, i32)> = fruits.iter()
+          .map(|(f, t, _, _)| (t.translation.truncate(), f.id))
+          .collect();
+        autoplayer.target = Some(pick_drop(active.0, &board, &table, &positions));
+      },
+      Strategy::Lookahead => match autoplayer.search.as_mut() {
+        None => {
+          let start = snapshot(&fruits, &active_fruit, &queue, &hold, &chain, &stats, &score, &rng);
+          let table = table.clone();
+          let sandbox = autoplayer.sandbox.take().map(SyncCell::to_inner);
+          let task = AsyncComputeTaskPool::get().spawn(async move {
+            let mut sandbox = sandbox.unwrap_or_else(|| Sandbox::new(&table));
+            let x = Lookahead::default().best_drop(&mut sandbox, &start, &table);
+            (x, sandbox)
+          });
+          autoplayer.search = Some(task);
+        },
+        Some(task) => if let Some((x, sandbox)) = future::block_on(future::poll_once(task)) {
+          autoplayer.target = Some(x);
+          autoplayer.search = None;
+          autoplayer.sandbox = Some(SyncCell::new(sandbox));
+        },
+      },
+    }
+  }
+  let Some(target) = autoplayer.target else {
+    return;
+  };
+
+  // steer to target, drop once there
+  let delta = target - transform.translation.x;
+  if delta.abs() < 0.01 {
+    controls.drop = true;
+    autoplayer.target = None;
+    autoplayer.wait = (settings.drop_cooldown / FIXED_DT) as u32;
+    autoplayer.settle_ticks = 0;
+  } else {
+    controls.move_dir = (delta / (settings.move_speed * FIXED_DT)).clamp(-1.0, 1.0);
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+// modes that start straight into a game. Puzzles need a level picked, versus its
+// own screen, and a daily game would count as the player's attempt
+fn restarts_in_place(mode: Res<GameMode>) -> bool {
+  matches!(*mode, GameMode::Classic | GameMode::Zen | GameMode::TimeAttack)
+}
+
+fn autoplayer_enabled(autoplayer: Res<AutoPlayer>) -> bool {
+  autoplayer.enabled
+}
+
+fn label_visibility(autoplayer: &AutoPlayer) -> Visibility {
+  if autoplayer.enabled {
+    Visibility::Visible
+  } else {
+    Visibility::Hidden
+  }
+}
+
+// best rated x for dropping fruit `id` onto the board
+pub fn pick_drop(id: i32, board: &[(Vec2, i32)], table: &FruitTable, positions: &Positions) -> f32 {
+  let limit = positions.cup_right_x - table.get(id).size / 2.0;
+  (0..CANDIDATES)
+    .map(|i| -limit + 2.0 * limit * i as f32 / (CANDIDATES - 1) as f32)
+    .map(|x| (x, rate_drop(id, x, board, table, positions)))
+    .fold((0.0, f32::MIN), |best, (x, rating)| if rating > best.1 { (x, rating) } else { best })
+    .0
+}
+
+// where a fruit dropped at x comes to rest, straight down onto the first fruit in
+// its way, and how good that is: merges first, then big fruits low and small ones
+// left uncovered
+fn rate_drop(id: i32, x: f32, board: &[(Vec2, i32)], table: &FruitTable, positions: &Positions) -> f32 {
+  let r = table.get(id).size / 2.0;
+  let mut landing = positions.cup_base_y + r;
+  let mut touching = None;
+  for &(pos, other) in board {
+    let reach = r + table.get(other).size / 2.0;
+    let dx = (pos.x - x).abs();
+    if dx < reach {
+      let y = pos.y + (reach * reach - dx * dx).sqrt();
+      if y > landing {
+        landing = y;
+        touching = Some(other);
+      }
+    }
+  }
+
+  let mut rating = -(landing - positions.cup_base_y) * (1.0 + 0.2 * id as f32);
+  match touching {
+    Some(other) if other == id => rating += 400.0,
+    Some(other) if other < id => rating -= 60.0 * (id - other) as f32,
+    _ => {},
+  }
+  // a same tier fruit right next to where it lands may still merge
+  let near = board.iter().any(|&(pos, other)| {
+    other == id && pos.distance(Vec2::new(x, landing)) < 2.4 * r
+  });
+  if near {
+    rating += 150.0;
+  }
+  if landing + r > positions.cup_max_y {
+    rating -= 1000.0;
+  }
+  rating
+}
@@ -0,0 +1,51 @@
+// headless simulation: plays scripted drops and prints the outcome
+// usage: suika-sim [--seed N] x1 x2 x3 ...
+//        suika-sim [--seed N] --bot heuristic|lookahead [--max-drops N]
+use suika_clone::Strategy;
+use suika_clone::sim::{headless_app, run_drops, run_autoplayer};
+
+fn main() {
+  let mut seed = 0;
+  let mut drops = Vec::new();
+  let mut bot = None;
+  let mut max_drops = 500;
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    if arg == "--seed" {
+      seed = args.next()
+        .and_then(|s| s.parse().ok())
+        .expect("Err: --seed needs a number");
+    } else if arg == "--bot" {
+      bot = match args.next().as_deref() {
+        Some("heuristic") => Some(Strategy::Heuristic),
+        Some("lookahead") => Some(Strategy::Lookahead),
+        _ => panic!("Err: --bot needs heuristic or lookahead"),
+      };
+    } else if arg == "--max-drops" {
+      max_drops = args.next()
+        .and_then(|s| s.parse().ok())
+        .expect("Err: --max-drops needs a number");
+    } else {
+      drops.push(arg.parse::<f32>().expect("Err: drop positions must be numbers"));
+    }
+  }
+
+  let mut app = headless_app(Some(seed));
+  let result = match bot {
+    Some(strategy) => run_autoplayer(&mut app, strategy, max_drops),
+    None => run_drops(&mut app, &drops),
+  };
+
+  println!("seed: {}", seed);
+  match bot {
+    Some(strategy) => println!("drops: {}/{} ({:?} bot)", result.drops, max_drops, strategy),
+    None => println!("drops: {}/{}", result.drops, drops.len()),
+  }
+  println!("score: {}", result.score);
+  println!("largest fruit: #{}", result.largest_fruit);
+  println!("longest chain: {}", result.longest_chain);
+  match result.reason {
+    Some(reason) => println!("ended: {}", reason),
+    None => println!("ended: all drops played"),
+  }
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+#[cfg(debug_assertions)]
+use bevy::input::touch::TouchPhase;
+
+use crate::util::{BG_COLOR, SCREEN_H, SCREEN_W, MainCamera};
+
+// spawns the 2d camera and keeps the cup in view when the window resizes
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+  fn build(&self, app: &mut App) {
+    app.insert_resource(ClearColor(BG_COLOR))
+      .add_systems(Startup, initialize)
+      .add_systems(Update, zoom_camera)
+      .add_systems(Update, mock_touch);
+  }
+}
+
+fn initialize(mut commands: Commands) {
+  // spawn camera
+  commands.spawn((Camera2dBundle::default(), MainCamera));
+}
+
+fn zoom_camera(
+  windows: Query<&Window>,
+  mut camera: Query<&mut Transform, With<MainCamera>>,
+) {
+  // note: only works if there's a single camera + single window
+  let Ok(window) = windows.get_single() else {
+    return;
+  };
+  for mut transform in camera.iter_mut() {
+
+    // rotate camera 90 deg if window h > window w
+    if window.height() > window.width() * 1.1 {
+      let delta_x = SCREEN_H / window.width();
+      let delta_y = SCREEN_W / window.height();
+      let delta = if delta_y > delta_x {
+        delta_y
+      } else {
+        delta_x
+      };
+      transform.scale = Vec3::new(delta, delta, 1.0);
+      transform.rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+    } else {
+      let delta_x = SCREEN_W / window.width();
+      let delta_y = SCREEN_H / window.height();
+      let delta = if delta_y > delta_x {
+        delta_y
+      } else {
+        delta_x
+      };
+      transform.scale = Vec3::new(delta, delta, 1.0);
+      transform.rotation = Quat::from_rotation_z(0.0);
+    }
+  }
+}
+
+// world position of a touch, None without a camera
+pub fn touch_to_world(
+  camera: &Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  position: Vec2,
+) -> Option<Vec2> {
+  let (camera, transform) = camera.get_single().ok()?;
+  camera.viewport_to_world_2d(transform, position)
+}
+
+#[cfg(debug_assertions)]
+pub fn mock_touch(
+  mouse: Res<Input<MouseButton>>,
+  windows: Query<&Window>,
+  mut touch_events: EventWriter<TouchInput>,
+) {
+  let Ok(window) = windows.get_single() else {
+    return;
+  };
+  let touch_phase = if mouse.just_pressed(MouseButton::Left) {
+    Some(TouchPhase::Started)
+  } else if mouse.just_released(MouseButton::Left) {
+    Some(TouchPhase::Ended)
+  } else if mouse.pressed(MouseButton::Left) {
+    Some(TouchPhase::Moved)
+  } else {
+    None
+  };
+  if let (Some(phase), Some(cursor_pos)) = (touch_phase, window.cursor_position()) {
+    touch_events.send(TouchInput {
+      phase,
+      position: cursor_pos,
+      force: None,
+      id: 0,
+    })
+  }
+}
+
+#[cfg(not(debug_assertions))]
+pub fn mock_touch() {}
@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+use bevy_persistent::prelude::*;
+
+use crate::util::{AppState, GameMode, GameSeed, DailyRun, DailyProgress, DailyHighScore, today, format_day};
+use crate::game::GameSet;
+use crate::save::ResumeGame;
+use crate::replay::use_replay_rules;
+
+pub struct DailyPlugin;
+
+impl Plugin for DailyPlugin {
+  fn build(&self, app: &mut App) {
+    // the menu shows today's table
+    app.add_systems(OnEnter(AppState::Menu), clear_old_scores)
+      // a continued game keeps the run it was saved with
+      .add_systems(OnEnter(AppState::InGame), start_daily
+        .before(GameSet::Reset)
+        // a replay picks the mode first
+        .after(use_replay_rules)
+        .run_if(resource_equals(GameMode::Daily))
+        .run_if(not(resource_exists::<ResumeGame>())));
+  }
+}
+
+// -- SYSTEMS --
+// the first game of the day counts, any after that are practice. So is a game
+// with a fixed seed, it isn't the day's fruits
+fn start_daily(
+  mut daily: ResMut<DailyRun>,
+  seed: Res<GameSeed>,
+  progress: Option<ResMut<Persistent<DailyProgress>>>,
+  highscore: Option<ResMut<Persistent<DailyHighScore>>>,
+) {
+  let day = today();
+  start_day(highscore, day);
+  let practice = match progress {
+    _ if seed.0.is_some() => true,
+    Some(mut progress) => {
+      let counted = progress.attempt(day);
+      if counted {
+        progress.persist().ok();
+      }
+      !counted
+    },
+    None => false,
+  };
+  *daily = DailyRun { day, practice };
+  println!("Daily challenge {}{}", format_day(day), if practice { " (practice)" } else { "" });
+}
+
+fn clear_old_scores(highscore: Option<ResMut<Persistent<DailyHighScore>>>) {
+  start_day(highscore, today());
+}
+
+// --- HELPER FUNCTIONS ---
+fn start_day(highscore: Option<ResMut<Persistent<DailyHighScore>>>, day: i64) {
+  if let Some(mut highscore) = highscore {
+    if highscore.start_day(day) {
+      highscore.persist().ok();
+    }
+  }
+}
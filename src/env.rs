@@ -0,0 +1,127 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::util::{AppState, Score, Fruit, GameSeed};
+use crate::game::{ActiveFruit, FruitQueue, Positions};
+use crate::sim::{headless_app, fast_settings, run_drops, clear_board};
+
+// gym style wrapper around a headless game, one step is one drop:
+//
+//   let mut env = SuikaEnv::new();
+//   let mut observation = env.reset(42);
+//   loop {
+//     let step = env.step(policy(&observation));
+//     if step.done { break; }
+//     observation = step.observation;
+//   }
+pub struct SuikaEnv {
+  app: App,
+  done: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FruitObservation {
+  pub tier: i32,
+  pub x: f32,
+  pub y: f32,
+  pub vx: f32,
+  pub vy: f32,
+}
+
+// None for active/next once the game is over
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+  pub fruits: Vec<FruitObservation>,
+  pub active: Option<i32>,
+  pub next: Option<i32>,
+  pub score: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct StepResult {
+  pub observation: Observation,
+  pub reward: i32, // score gained by the drop
+  pub done: bool,
+}
+
+impl SuikaEnv {
+  pub fn new() -> Self {
+    let mut app = headless_app(Some(0));
+    app.insert_resource(fast_settings());
+    let mut env = SuikaEnv { app, done: false };
+    env.start();
+    env
+  }
+
+  // starts a new game on a fresh board, the app is kept between games
+  pub fn reset(&mut self, seed: u64) -> Observation {
+    clear_board(&mut self.app);
+    self.app.insert_resource(GameSeed(Some(seed)));
+    self.start();
+    self.observe()
+  }
+
+  fn start(&mut self) {
+    self.app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+    // enter the game and spawn the first active fruit
+    self.app.update();
+    self.app.update();
+    self.done = false;
+  }
+
+  // drops the active fruit at x (clamped to the cup) and waits for the board to settle
+  pub fn step(&mut self, drop_x: f32) -> StepResult {
+    if self.done {
+      return StepResult { observation: self.observe(), reward: 0, done: true };
+    }
+    let before = self.app.world.resource::<Score>().0;
+    let result = run_drops(&mut self.app, &[drop_x]);
+    self.done = result.reason.is_some();
+    StepResult {
+      observation: self.observe(),
+      reward: result.score - before,
+      done: self.done,
+    }
+  }
+
+  pub fn observe(&mut self) -> Observation {
+    let world = &mut self.app.world;
+    let fruits = world.query::<(&Fruit, &Transform, &Velocity)>()
+      .iter(world)
+      .map(|(fruit, transform, velocity)| FruitObservation {
+        tier: fruit.id,
+        x: transform.translation.x,
+        y: transform.translation.y,
+        vx: velocity.linvel.x,
+        vy: velocity.linvel.y,
+      })
+      .collect();
+    let active = world.query::<&ActiveFruit>().iter(world).next().map(|a| a.0);
+    let next = if self.done {
+      None
+    } else {
+      world.resource::<FruitQueue>().fruits.front().copied()
+    };
+    Observation {
+      fruits,
+      active,
+      next,
+      score: world.resource::<Score>().0,
+    }
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.done
+  }
+
+  // cup walls, for scaling drop positions and observations
+  pub fn positions(&self) -> &Positions {
+    self.app.world.resource::<Positions>()
+  }
+}
+
+impl Default for SuikaEnv {
+  fn default() -> Self {
+    SuikaEnv::new()
+  }
+}
@@ -1,15 +1,32 @@
-use std::time::Duration;
-use bevy::{prelude::*, sprite::MaterialMesh2dBundle, input::touch::TouchPhase};
+use std::{collections::VecDeque, ops::DerefMut, time::Duration};
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle, input::touch::TouchPhase, window::WindowFocused};
 use bevy_rapier2d::prelude::*;
 use bevy_persistent::prelude::Persistent;
 use rand::Rng;
+use serde::{Serialize, Deserialize};
 
+use crate::camera::touch_to_world;
+use crate::settings::{Settings, MoveSpeedOverride};
 use crate::util::{
   AppState,
+  PauseState,
+  MainCamera,
   Score,
   Fruit,
   CoolDown,
   HighScore,
+  TimeAttackHighScore,
+  DailyHighScore,
+  DailyRun,
+  Leaderboard,
+  GameMode,
+  NewHighScore,
+  ScoreEntry,
+  LifetimeStats,
+  GameRng,
+  GameSeed,
+  GameOverReason,
+  OffendingFruit,
   // SCREEN_W,
   SCREEN_H,
   CONTAINER_W,
@@ -20,59 +37,328 @@ use crate::util::{
   CUP_BG_COLOR,
   OVERLAY_COLOR,
   MAX_H_COLOR,
-  SUIKA,
+  FruitTable,
   HOLD_POS,
   HOLD_POS_FRUIT,
+  QUEUE_POS,
+  SWAP_POS,
+  SWAP_POS_FRUIT,
   LEGEND_POS,
-  MOVE_SPEED,
+  CLOCK_POS,
+  PAUSE_BUTTON_POS,
   GRAVITY,
   RESTITUATION,
   MIN_SPEED,
-  CLICK_DELAY,
+  PREVIEW_COUNT,
+  CHAIN_WINDOW,
+  CHAIN_LINK_WINDOW,
+  MAX_MULTIPLIER,
+  MAX_HIGH_SCORES,
+  ZEN_PENALTY,
+  LINE_TIMEOUT,
   TEXT_COLOR, 
   FRICTION,
   DAMPENING,
+  FIXED_DT,
+  PIXELS_PER_METER,
+  now_timestamp,
+  format_date,
+  format_duration,
+  format_day,
+  daily_seed,
 };
 
 pub struct InGamePlugin;
 
 impl Plugin for InGamePlugin {
   fn build(&self, app: &mut App) {
-    app.insert_resource(Positions {
+    app.add_state::<AppState>()
+      .add_state::<PauseState>()
+      // no-op with a window, keeps headless apps working
+      .add_event::<WindowFocused>()
+      .init_resource::<Score>()
+      .insert_resource(Positions {
         cup_base_y: -0.5 * CONTAINER_H - CONTAINER_P,
         cup_max_y: 0.5 * CONTAINER_H - CONTAINER_P,
         cup_left_x: -CONTAINER_W / 2.0,
         cup_right_x: CONTAINER_W / 2.0,
       })
-      .add_systems(Startup, (spawn_cup, spawn_permanent_ui))
-      .add_systems(OnEnter(AppState::InGame), reset_game_state)
-      .add_systems(Update, (
+      .init_resource::<GameSeed>()
+      .init_resource::<GameRng>()
+      .init_resource::<FruitQueue>()
+      .init_resource::<HoldSlot>()
+      .init_resource::<Chain>()
+      .init_resource::<GameStats>()
+      .init_resource::<GameMode>()
+      .init_resource::<DailyRun>()
+      .add_event::<FruitDropped>()
+      .add_event::<FruitMerged>()
+      .add_event::<GameOver>()
+      .init_resource::<PendingCollisions>()
+      .init_resource::<Settings>()
+      .add_systems(Startup, (load_fruit_textures, (spawn_cup, spawn_permanent_ui)).chain());
+    // headless apps can bring their own table
+    if !app.world.contains_resource::<FruitTable>() {
+      app.insert_resource(FruitTable::load());
+    }
+    app
+      .add_systems(OnEnter(AppState::InGame), reset_game_state.in_set(GameSet::Reset))
+      // a paused game skips whole ticks, replays and bots included
+      .configure_sets(FixedUpdate, (
+          GameSet::Input.run_if(in_state(PauseState::Running)),
+          GameSet::Control.run_if(in_state(PauseState::Running)),
+          GameSet::Logic.run_if(in_state(PauseState::Running)),
+        ).chain())
+      .configure_set(Update, GameSet::Input.run_if(in_state(PauseState::Running)))
+      .add_systems(Update, (handle_inputs, pause_on_focus_lost)
+        .in_set(GameSet::Input)
+        .run_if(in_state(AppState::InGame)))
+      .add_systems(FixedUpdate, latch_inputs
+        .in_set(GameSet::Input)
+        .run_if(in_state(AppState::InGame)))
+      .add_systems(FixedUpdate, (
           end_game,
-          handle_inputs,
-          handle_active_fruit.before(handle_next_fruit),
-          handle_next_fruit,
-          handle_merging,
-          update_score,
-        ).run_if(in_state(AppState::InGame)))
-      .add_systems(OnExit(AppState::InGame), pause_state);
+          // systems sharing GameRng run in a fixed order to keep games reproducible
+          (
+            handle_active_fruit,
+            handle_next_fruit,
+            handle_held_fruit,
+            handle_merging,
+            track_game_stats,
+          ).chain(),
+        ).in_set(GameSet::Logic).run_if(in_state(AppState::InGame)))
+      // keeps rapier's collisions for handle_merging, which needs FixedPhysicsPlugin
+      // to step the world in FixedUpdate
+      .add_systems(FixedUpdate, buffer_collisions.after(PhysicsSet::Writeback))
+      .add_systems(Update, (update_score, update_chain, update_clock).run_if(in_state(AppState::InGame)))
+      .add_systems(Update, (
+          update_high_score_list::<HighScore>.run_if(resource_equals(GameMode::Classic)),
+          update_high_score_list::<TimeAttackHighScore>.run_if(resource_equals(GameMode::TimeAttack)),
+          update_high_score_list::<DailyHighScore>.run_if(resource_equals(GameMode::Daily)),
+          clear_high_score_list
+            .run_if(resource_equals(GameMode::Zen)
+              .or_else(resource_equals(GameMode::Puzzle))
+              .or_else(resource_equals(GameMode::Versus)))
+            .run_if(resource_changed::<GameMode>()),
+          record_lifetime_stats,
+        ))
+      .add_systems(Update, scale_ui.run_if(resource_changed::<Settings>()))
+      .add_systems(OnEnter(PauseState::Paused), stop_physics)
+      .add_systems(OnExit(PauseState::Paused), resume_game)
+      .add_systems(OnExit(AppState::InGame), (
+          announce_game_over.run_if(resource_exists::<GameOverReason>()),
+          pause_state,
+          record_high_score::<HighScore>.run_if(resource_equals(GameMode::Classic)),
+          record_high_score::<TimeAttackHighScore>.run_if(resource_equals(GameMode::TimeAttack)),
+          record_high_score::<DailyHighScore>
+            .run_if(resource_equals(GameMode::Daily))
+            .run_if(|daily: Res<DailyRun>| !daily.practice)
+            // a game continued from an earlier day has no table left to go in
+            .run_if(|daily: Res<DailyRun>, highscore: Option<Res<Persistent<DailyHighScore>>>| {
+              highscore.is_none_or(|h| h.day == daily.day)
+            }),
+        ).chain());
+  }
+}
+
+// steps rapier in FixedUpdate after the game logic, so a 144hz and a 60hz
+// player get the same simulation
+pub struct FixedPhysicsPlugin;
+
+impl Plugin for FixedPhysicsPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(PIXELS_PER_METER)
+        .with_default_system_setup(false))
+      .insert_resource(FixedTime::new_from_secs(FIXED_DT))
+      .configure_sets(FixedUpdate, (
+          PhysicsSet::SyncBackend,
+          PhysicsSet::SyncBackendFlush,
+          PhysicsSet::StepSimulation,
+          PhysicsSet::Writeback,
+        ).chain().after(GameSet::Logic))
+      .add_systems(FixedUpdate, (
+          RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend)
+            .in_set(PhysicsSet::SyncBackend),
+          RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackendFlush)
+            .in_set(PhysicsSet::SyncBackendFlush),
+          RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation)
+            .in_set(PhysicsSet::StepSimulation),
+          RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback)
+            .in_set(PhysicsSet::Writeback),
+          // despawns have to reach rapier in the same tick, its removal events are
+          // cleared every frame and a fast screen can go a few frames without a tick
+          apply_deferred.after(GameSet::Logic).before(PhysicsSet::SyncBackend),
+        ));
+    app.world.resource_mut::<RapierConfiguration>().timestep_mode = TimestepMode::Fixed {
+      dt: FIXED_DT,
+      substeps: 1,
+    };
   }
 }
 
+// devices are read every frame in Update (Input). Each FixedUpdate tick then latches
+// them (Input), lets replays/bots override Controls (Control) and runs the game (Logic).
+// Logic only reads Controls. Reset runs on entering InGame, anything that sets up the
+// board goes after it
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GameSet {
+  Reset,
+  Input,
+  Control,
+  Logic,
+}
+
 // -- RESOURCES --
 #[derive(Resource)]
-struct Positions {
-  cup_base_y: f32,
-  cup_max_y: f32,
-  cup_left_x: f32,
-  cup_right_x: f32,
+pub struct Positions {
+  pub cup_base_y: f32,
+  pub cup_max_y: f32,
+  pub cup_left_x: f32,
+  pub cup_right_x: f32,
+}
+
+// upcoming fruits, the active fruit is always popped from the front. Insert
+// FruitQueue::new(n) before InGamePlugin to preview a different number of them
+#[derive(Resource, Clone, Debug)]
+pub struct FruitQueue {
+  pub size: usize,
+  pub fruits: VecDeque<i32>,
+  // drawn from instead of the rng when set (puzzle levels), runs out
+  pub fixed: Option<VecDeque<i32>>,
+}
+
+impl FruitQueue {
+  pub fn new(size: usize) -> Self {
+    FruitQueue { size: size.max(1), fruits: VecDeque::new(), fixed: None }
+  }
+
+  // tops the queue up with random spawnable tiers, or what's left of the fixed ones
+  pub fn fill(&mut self, rng: &mut GameRng, table: &FruitTable) {
+    while self.fruits.len() < self.size {
+      let id = match self.fixed.as_mut() {
+        Some(fixed) => match fixed.pop_front() {
+          Some(id) => id,
+          None => break,
+        },
+        None => rng.gen_range(0..table.spawn_tiers),
+      };
+      self.fruits.push_back(id);
+    }
+  }
+
+  // None once a fixed sequence has run out
+  pub fn try_pop(&mut self, rng: &mut GameRng, table: &FruitTable) -> Option<i32> {
+    self.fill(rng, table);
+    let id = self.fruits.pop_front();
+    self.fill(rng, table);
+    id
+  }
+
+  pub fn pop(&mut self, rng: &mut GameRng, table: &FruitTable) -> i32 {
+    self.try_pop(rng, table).unwrap_or_default()
+  }
+
+  pub fn is_exhausted(&self) -> bool {
+    self.fruits.is_empty() && self.fixed.as_ref().is_some_and(|fixed| fixed.is_empty())
+  }
+}
+
+impl Default for FruitQueue {
+  fn default() -> Self {
+    FruitQueue::new(PREVIEW_COUNT)
+  }
+}
+
+// collisions from the last physics step, merged on the next tick
+#[derive(Resource, Default)]
+pub(crate) struct PendingCollisions(pub(crate) Vec<CollisionEvent>);
+
+// fruit put aside with the hold control, swapped at most once per drop
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct HoldSlot {
+  pub fruit: Option<i32>,
+  pub swapped: bool,
+}
+
+// merges close together, or set off by the previous merge's product, build a
+// chain and each merge in it scores the fruit score times the chain length
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Chain {
+  pub id: u32,
+  pub count: i32,
+  pub since_merge: f32,
+  pub longest: i32,
+}
+
+impl Chain {
+  pub fn multiplier(&self) -> i32 {
+    self.count.clamp(1, MAX_MULTIPLIER)
+  }
+
+  // chain is still shown and can be extended by any merge
+  pub fn is_active(&self) -> bool {
+    self.count > 1 && self.since_merge <= CHAIN_WINDOW
+  }
+
+  // counts a merge, `linked` if one of its fruits came from this chain
+  pub fn extend(&mut self, linked: bool) {
+    if self.since_merge <= CHAIN_WINDOW || (linked && self.since_merge <= CHAIN_LINK_WINDOW) {
+      self.count += 1;
+    } else {
+      self.id += 1;
+      self.count = 1;
+    }
+    self.since_merge = 0.0;
+    self.longest = self.longest.max(self.count);
+  }
+}
+
+// numbers of the current game that end up on the leaderboard
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct GameStats {
+  pub duration: f32, // seconds of unpaused play
+  pub drops: u32,
+  pub highest_tier: i32,
+}
+
+// -- EVENTS --
+// active fruit let go at x, `entity` is the new physics body
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FruitDropped {
+  pub entity: Entity,
+  pub id: i32,
+  pub x: f32,
+  // versus player whose cup it was, None in the single player game
+  pub player: Option<usize>,
+}
+
+// two `input_id` fruits became one `output_id` fruit at `position`
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FruitMerged {
+  pub inputs: [Entity; 2],
+  pub input_id: i32,
+  pub output: Entity,
+  pub output_id: i32,
+  pub position: Vec2,
+  pub score: i32,
+  pub player: Option<usize>,
+}
+
+// sent once as the game is left, quitting included. A versus match sends one per player
+#[derive(Event, Clone, Copy, Debug)]
+pub struct GameOver {
+  pub reason: GameOverReason,
+  pub score: i32,
+  pub player: Option<usize>,
+  pub stats: GameStats,
 }
 
 // -- COMPONENTS --
 #[derive(Component)]
-struct UIComponent;
+pub(crate) struct UIComponent;
 
 #[derive(Component)]
-struct PermUIComponent;
+pub(crate) struct PermUIComponent;
 
 #[derive(Component)]
 struct UIScore;
@@ -80,42 +366,83 @@ struct UIScore;
 #[derive(Component)]
 struct UIHighScore;
 
+#[derive(Component)]
+struct UIChain;
+
 #[derive(Component)]
 struct UIControls;
 
+
 #[derive(Component)]
 struct UIHighScoreList(usize);
 
 #[derive(Component)]
-struct Cup;
+struct UIHighScoreTitle;
+
+// time left in modes with a time limit
+#[derive(Component)]
+struct UIClock;
+
+#[derive(Component, Clone)]
+pub struct Cup;
 
 #[derive(Component)]
-struct ActiveFruit(i32);
+pub struct ActiveFruit(pub i32);
 
 #[derive(Component)]
-struct NextFruit(i32);
+pub struct NextFruit(pub i32);
 
 #[derive(Component)]
-struct PreviewBar;
+pub struct HeldFruit(pub i32);
 
+// fruit made by a merge of chain `0`
 #[derive(Component)]
-struct Timeout;
-
-#[derive(Component, Debug)]
-struct Controls {
-  move_dir: f32,
-  drop_lock: bool,
-  drop: bool,
-  end_game: bool,
+pub struct ChainLink(pub u32);
+
+// fruit, active or dropped, in versus player `0`'s cup
+#[derive(Component, Clone, Copy, Debug)]
+pub struct OnBoard(pub usize);
+
+#[derive(Component)]
+pub struct PreviewBar;
+
+#[derive(Component)]
+pub struct Timeout;
+
+#[derive(Component, Debug, Default)]
+pub struct Controls {
+  pub move_dir: f32,
+  pub(crate) drop_lock: bool,
+  pub(crate) drop_queued: bool,
+  pub drop: bool,
+  pub(crate) hold_queued: bool,
+  pub hold: bool,
+  pub end_game: bool,
   touch_id: u64,
   touch_start: Vec2,
 }
 
 // -- SYSTEMS --
+fn load_fruit_textures(
+  asset_server: Res<AssetServer>,
+  mut table: ResMut<FruitTable>,
+) {
+  let table = table.as_mut();
+  table.textures = table.texture_paths.iter()
+    .map(|path| path.as_ref().map(|p| asset_server.load(p.as_str())))
+    .collect();
+}
+
 fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
+  spawn_cup_at(&mut commands, &positions, Cup);
+}
+
+// walls, background and max height line around `positions`, each tagged with `marker`
+pub(crate) fn spawn_cup_at(commands: &mut Commands, positions: &Positions, marker: impl Component + Clone) {
+  let center_x = 0.5 * (positions.cup_left_x + positions.cup_right_x);
   let container_base = positions.cup_base_y - 0.5 * CONTAINER_T;
   commands.spawn((
-    Cup,
+    marker.clone(),
     Collider::cuboid(CONTAINER_W / 2.0, CONTAINER_T / 2.0),
     SpriteBundle {
       sprite: Sprite {
@@ -123,7 +450,7 @@ fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
         color: CONTAINER_COLOR,
         ..default()
       },
-      transform: Transform::from_xyz(0.0, container_base, 0.0),
+      transform: Transform::from_xyz(center_x, container_base, 0.0),
       ..default()
     },
   ));
@@ -132,7 +459,7 @@ fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
   let wall_base = container_base + 0.5 * CONTAINER_H;
   // spawn left wall
   commands.spawn((
-    Cup,
+    marker.clone(),
     Collider::cuboid(CONTAINER_T / 2.0, wall_h / 2.0),
     SpriteBundle {
       sprite: Sprite {
@@ -150,7 +477,7 @@ fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
   ));
   // spawn right wall
   commands.spawn((
-    Cup,
+    marker.clone(),
     Collider::cuboid(CONTAINER_T / 2.0, wall_h / 2.0),
     SpriteBundle {
       sprite: Sprite {
@@ -168,24 +495,23 @@ fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
   ));
 
   // spawn background
-  let bg_x = positions.cup_right_x + positions.cup_left_x;
   let bg_y = positions.cup_max_y + positions.cup_base_y + CONTAINER_T * 2.0;
   commands.spawn((
-    Cup,
+    marker.clone(),
     SpriteBundle {
       sprite: Sprite {
         custom_size: Some(Vec2::new(CONTAINER_W, CONTAINER_H)),
         color: CUP_BG_COLOR,
         ..default()
       },
-      transform: Transform::from_xyz(bg_x, bg_y, -3.0),
+      transform: Transform::from_xyz(center_x, bg_y, -3.0),
       ..default()
     },
   ));
 
   // render unmovable zone left
   commands.spawn((
-    Cup,
+    marker.clone(),
     SpriteBundle {
       sprite: Sprite {
         custom_size: Some(Vec2::new(CONTAINER_P, wall_h)),
@@ -202,7 +528,7 @@ fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
   ));
   // render unmovable zone right
   commands.spawn((
-    Cup,
+    marker.clone(),
     SpriteBundle {
       sprite: Sprite {
         custom_size: Some(Vec2::new(CONTAINER_P, wall_h)),
@@ -220,14 +546,14 @@ fn spawn_cup(mut commands: Commands, positions: Res<Positions>) {
 
   // render max height line
   commands.spawn((
-    Cup,
+    marker.clone(),
     SpriteBundle {
       sprite: Sprite {
         custom_size: Some(Vec2::new(CONTAINER_W + CONTAINER_T * 2.0, 1.5)),
         color: MAX_H_COLOR,
         ..default()
       },
-      transform: Transform::from_xyz(0.0, positions.cup_max_y + 0.75, -3.0),
+      transform: Transform::from_xyz(center_x, positions.cup_max_y + 0.75, -3.0),
       ..default()
     },
   ));
@@ -237,15 +563,19 @@ fn spawn_permanent_ui(
   mut commands: Commands, 
   mut meshes: ResMut<Assets<Mesh>>,
   mut materials: ResMut<Assets<ColorMaterial>>,
-  asset_server: Res<AssetServer>,
+  table: Res<FruitTable>,
   score: Res<Score>,
-  highscore: Res<Persistent<HighScore>>,
+  highscore: Option<Res<Persistent<HighScore>>>,
 ) {
+  // room for the largest fruit that can come next, and the one it merges into
+  let next_r = table.get(table.largest_spawn_id()).size;
+  let score_r = table.get(table.largest_spawn_id() + 1).size;
+
   // render hold area
   commands.spawn((
     PermUIComponent,
     MaterialMesh2dBundle {
-      mesh: meshes.add(shape::Circle::new(SUIKA[4].size).into()).into(),
+      mesh: meshes.add(shape::Circle::new(next_r).into()).into(),
       material: materials.add(ColorMaterial::from(OVERLAY_COLOR)),
       transform: Transform::from_translation(HOLD_POS),
       ..default()
@@ -261,7 +591,31 @@ fn spawn_permanent_ui(
           ..default()
         }
       ),
-      transform: Transform::from_translation(Vec3::new(0.0, SUIKA[4].size, 0.0)),
+      transform: Transform::from_translation(Vec3::new(0.0, next_r, 0.0)),
+      ..default()
+    });
+  });
+
+  // render swap area
+  commands.spawn((
+    PermUIComponent,
+    MaterialMesh2dBundle {
+      mesh: meshes.add(shape::Circle::new(45.0).into()).into(),
+      material: materials.add(ColorMaterial::from(OVERLAY_COLOR)),
+      transform: Transform::from_translation(SWAP_POS),
+      ..default()
+    }
+  )).with_children(|root| {
+    root.spawn(Text2dBundle {
+      text: Text::from_section(
+        "Hold",
+        TextStyle {
+          font_size: 30.0,
+          color: TEXT_COLOR,
+          ..default()
+        }
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 60.0, 0.0)),
       ..default()
     });
   });
@@ -270,7 +624,7 @@ fn spawn_permanent_ui(
   commands.spawn((
     PermUIComponent,
     MaterialMesh2dBundle {
-      mesh: meshes.add(shape::Circle::new(SUIKA[5].size).into()).into(),
+      mesh: meshes.add(shape::Circle::new(score_r).into()).into(),
       material: materials.add(ColorMaterial::from(OVERLAY_COLOR)),
       transform: Transform::from_translation(Vec3::new(-HOLD_POS.x, HOLD_POS.y, 0.0)),
       ..default()
@@ -286,7 +640,7 @@ fn spawn_permanent_ui(
           ..default()
         }
       ),
-      transform: Transform::from_translation(Vec3::new(0.0, SUIKA[5].size, 0.0)),
+      transform: Transform::from_translation(Vec3::new(0.0, score_r, 0.0)),
       ..default()
     });
     // score render
@@ -328,6 +682,19 @@ fn spawn_permanent_ui(
       transform: Transform::from_translation(Vec3::new(0.0, -35.0, 0.0)),
       ..default()
     }, UIHighScore));
+    // chain render
+    root.spawn((Text2dBundle {
+      text: Text::from_section(
+        "",
+        TextStyle {
+          font_size: 26.0,
+          color: TEXT_COLOR,
+          ..default()
+        }
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, -65.0, 0.0)),
+      ..default()
+    }, UIChain));
   });
 
   // render highscore area
@@ -341,7 +708,7 @@ fn spawn_permanent_ui(
     }
   )).with_children(|root| {
     // render title
-    root.spawn(Text2dBundle {
+    root.spawn((Text2dBundle {
       text: Text::from_section(
         "High Scores:",
         TextStyle {
@@ -350,40 +717,70 @@ fn spawn_permanent_ui(
           ..default()
         }
       ),
-      transform: Transform::from_translation(Vec3::new(0.0, 140.0, 0.0)),
+      transform: Transform::from_translation(Vec3::new(0.0, 145.0, 0.0)),
       ..default()
-    });
-    // render updated high scores
-    for (i, hscore) in highscore.0.iter().enumerate() {
-      let y = 80.0 - i as f32 * 30.0;
+    }, UIHighScoreTitle));
+    // render high scores, name and score over the details of each game
+    let highscore = highscore.map(|h| h.get().clone()).unwrap_or_default();
+    for i in 0..MAX_HIGH_SCORES {
+      let y = 108.0 - i as f32 * 35.0;
+      let (title, details) = high_score_row(highscore.entries.get(i), i);
       root.spawn((Text2dBundle {
-        text: Text::from_section(
-          hscore.to_string(),
-          TextStyle {
-            font_size: 30.0,
+        text: Text::from_sections([
+          TextSection::new(title, TextStyle {
+            font_size: 20.0,
             color: TEXT_COLOR,
             ..default()
-          }
-        ),
+          }),
+          TextSection::new(details, TextStyle {
+            font_size: 14.0,
+            color: TEXT_COLOR,
+            ..default()
+          }),
+        ]).with_alignment(TextAlignment::Center),
         transform: Transform::from_translation(Vec3::new(0.0, y, 10.0)),
         ..default()
       }, UIHighScoreList(i)));
     };
   });
 
-  // render legend
+  // render legend: tiers clockwise around a circle, smallest at the top right
+  let legend_r = 105.0;
+  let arc = 300_f32.to_radians();
+  let step = if table.len() > 1 { arc / (table.len() - 1) as f32 } else { 0.0 };
+  let dot_r = if table.len() > 1 { f32::min(17.5, legend_r * step * 0.45) } else { 17.5 };
   commands.spawn((
     PermUIComponent,
-    SpriteBundle {
-      texture: asset_server.load("suika_clone_legend.png"),
-      transform: Transform {
-        translation: LEGEND_POS,
-        scale: Vec3::new(0.7, 0.7, 1.0), 
-        ..default()
-      },
+    MaterialMesh2dBundle {
+      mesh: meshes.add(shape::Circle::new(legend_r + 30.0).into()).into(),
+      material: materials.add(ColorMaterial::from(OVERLAY_COLOR)),
+      transform: Transform::from_translation(LEGEND_POS),
       ..default()
     },
-  ));
+  )).with_children(|root| {
+    for fruit in table.fruits.iter() {
+      let angle = 60_f32.to_radians() - step * fruit.id as f32;
+      root.spawn(MaterialMesh2dBundle {
+        mesh: meshes.add(shape::Circle::new(dot_r).into()).into(),
+        material: materials.add(table.material(fruit.id)),
+        transform: Transform::from_xyz(legend_r * angle.cos(), legend_r * angle.sin(), 0.1),
+        ..default()
+      }).with_children(|dot| {
+        dot.spawn(Text2dBundle {
+          text: Text::from_section(
+            fruit.id.to_string(),
+            TextStyle {
+              font_size: 18.0,
+              color: TEXT_COLOR,
+              ..default()
+            }
+          ),
+          transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.1)),
+          ..default()
+        });
+      });
+    }
+  });
 
   // render controls explanation
   let controls_x = 0.0; // SCREEN_W / 4.0;
@@ -394,7 +791,7 @@ fn spawn_permanent_ui(
     Text2dBundle {
       text: Text::from_sections([
         TextSection {
-          value: "Arrow keys: move | Space: drop | Esc: quit | Touch id:".to_string(), 
+          value: "Arrow keys: move | Space: drop | C: hold | Esc: pause | Touch id:".to_string(), 
           style: TextStyle {
             font_size: 18.0,
             color: TEXT_COLOR,
@@ -421,22 +818,108 @@ fn reset_game_state(
   mut score: ResMut<Score>,
   mut rapier_config: ResMut<RapierConfiguration>,
   mut highscore_q: Query<&mut Text, With<UIHighScore>>,
+  seed: Res<GameSeed>,
+  mut rng: ResMut<GameRng>,
+  mut queue: ResMut<FruitQueue>,
+  mut hold: ResMut<HoldSlot>,
+  mut chain: ResMut<Chain>,
+  mut stats: ResMut<GameStats>,
+  mut collisions: ResMut<PendingCollisions>,
+  mode: Res<GameMode>,
+  daily: Res<DailyRun>,
+  settings: Res<Settings>,
 ) {
   // insantiate controls
   commands.spawn((
     Controls { 
       move_dir:0.0,
       drop_lock:false,
+      drop_queued:false,
       drop:false,
+      hold_queued:false,
+      hold:false,
       end_game:false, 
       touch_id:0,
       touch_start:Vec2::new(0.0, 0.0),
     },
-    CoolDown{ timer:Timer::new(Duration::from_secs_f32(CLICK_DELAY), TimerMode::Once) }
+    CoolDown{ timer:Timer::new(Duration::from_secs_f32(settings.drop_cooldown), TimerMode::Once) }
+  ));
+
+  // re-seed rng, a fixed seed (--seed or a replay) wins over the day's
+  *rng = match (*mode, seed.0) {
+    (_, Some(s)) => GameRng::new(s),
+    (GameMode::Daily, None) => GameRng::new(daily_seed(daily.day)),
+    (_, None) => GameRng::from_entropy(),
+  };
+  queue.fruits.clear();
+  queue.fixed = None;
+  *hold = HoldSlot::default();
+  *chain = Chain::default();
+  *stats = GameStats::default();
+  collisions.0.clear();
+
+
+  // pause button for touch screens
+  commands.spawn((
+    UIComponent,
+    Text2dBundle {
+      text: Text::from_section(
+        "II",
+        TextStyle {
+          font_size: 30.0,
+          color: TEXT_COLOR,
+          ..default()
+        }
+      ),
+      transform: Transform::from_translation(PAUSE_BUTTON_POS),
+      ..default()
+    },
   ));
 
+  // countdown, filled in by update_clock
+  if mode.time_limit().is_some() {
+    commands.spawn((
+      UIComponent,
+      UIClock,
+      Text2dBundle {
+        text: Text::from_section(
+          "",
+          TextStyle {
+            font_size: 30.0,
+            color: TEXT_COLOR,
+            ..default()
+          }
+        ),
+        transform: Transform::from_translation(CLOCK_POS),
+        ..default()
+      },
+    ));
+  }
+
+  // which day's challenge this is
+  if *mode == GameMode::Daily {
+    let practice = if daily.practice { "\npractice run" } else { "" };
+    commands.spawn((
+      UIComponent,
+      Text2dBundle {
+        text: Text::from_section(
+          format!("Daily {}{}", format_day(daily.day), practice),
+          TextStyle {
+            font_size: 24.0,
+            color: TEXT_COLOR,
+            ..default()
+          }
+        ),
+        transform: Transform::from_translation(CLOCK_POS),
+        ..default()
+      },
+    ));
+  }
+
   // reset score
   score.0 = 0;
+  commands.remove_resource::<GameOverReason>();
+  commands.remove_resource::<NewHighScore>();
   // update highscore
   if let Ok(text) = highscore_q.get_single_mut() {
     text.into_inner().sections[0].value = score.1.to_string();
@@ -452,20 +935,30 @@ fn end_game(
   positions: Res<Positions>,
   mut next_state: ResMut<NextState<AppState>>,
   controls: Query<&Controls>,
-  spawned_fruits: Query<(&Transform, &Velocity, &Fruit)>,
+  spawned_fruits: Query<(Entity, &Transform, &Velocity, &Fruit)>,
   mut time_out: Query<(Entity, &mut CoolDown), With<Timeout>>,
-  time: Res<Time>,
+  fixed_time: Res<FixedTime>,
+  mode: Res<GameMode>,
+  stats: Res<GameStats>,
+  mut score: ResMut<Score>,
 ) {
   let input = controls.single();
+  // first reason found wins
+  let mut reason = None;
+  let mut timer_spawned = false;
   // quick exit
   if input.end_game {
-    next_state.set(AppState::GameOver);
+    reason = Some(GameOverReason::Quit);
+  }
+  // out of time
+  if mode.time_limit().is_some_and(|limit| stats.duration >= limit) {
+    reason = reason.or(Some(GameOverReason::TimeUp));
   }
 
   // tick timer
   if let Ok((e, cd)) = time_out.get_single_mut() {
     let timer = &mut cd.into_inner().timer;
-    timer.tick(time.delta());
+    timer.tick(fixed_time.period);
 
     // delete timer if time is over
     if timer.finished() {
@@ -474,40 +967,44 @@ fn end_game(
   }
 
   // find if fruit has exceeded limits
-  let max_h = positions.cup_max_y;
-  let max_x = positions.cup_right_x + CONTAINER_T;
-  for (fruit_t, fruit_v, fruit) in spawned_fruits.iter() {
-    if fruit_t.translation.x > max_x {
-      println!("Game Over: fruit has gone outside right boundary {}", fruit_t.translation.x);
-      next_state.set(AppState::GameOver);
-    }
-    if fruit_t.translation.x < -max_x {
-      println!("Game Over: fruit has gone outside left boundary {}", fruit_t.translation.x);
-      next_state.set(AppState::GameOver);
-    }
-    
-    let scalar_v = fruit_v.linvel.length();
-    if scalar_v.abs() < MIN_SPEED && fruit_t.translation.y > max_h - (0.4 * fruit.size) {
+  for (entity, fruit_t, fruit_v, fruit) in spawned_fruits.iter() {
+    let mut overflow = fruit_overflow(&positions, entity, fruit_t, fruit_v, fruit);
+    if let Some(GameOverReason::MaxHeight(_)) = overflow {
       // get timeout timer
       match time_out.get_single() {
-        Ok((_, cooldown)) => {
-          if cooldown.timer.finished() {
-            println!("Game Over: fruit has reached max height");
-            next_state.set(AppState::GameOver);
-          } else {
-            println!("Game Over imminent: fruit is past max height");
+        Ok((_, cooldown)) if cooldown.timer.finished() => {},
+        Ok(_) => overflow = None,
+        Err(_) => {
+          // spawn timeout timer, once for all fruits above the line
+          if !timer_spawned {
+            commands.spawn((
+              CoolDown {timer:Timer::from_seconds(LINE_TIMEOUT, TimerMode::Once)},
+              Timeout
+            ));
+            timer_spawned = true;
           }
+          overflow = None;
         },
-        Err(_) => {
-          // spawn timeout timer
-          commands.spawn((
-            CoolDown {timer:Timer::from_seconds(0.5, TimerMode::Once)},
-            Timeout
-          ));
-        }
       }
     }
+
+    // zen quietly takes the fruit away instead
+    if overflow.is_some() && !mode.overflow_ends_game() {
+      commands.entity(entity).despawn_recursive();
+      score.0 = (score.0 - ZEN_PENALTY).max(0);
+    } else {
+      reason = reason.or(overflow);
+    }
   };
+
+  if let Some(reason) = reason {
+    // zen never gets here for an overflowing fruit, it was taken away above
+    if let Some(fruit) = reason.fruit() {
+      println!("Game Over: #{} {} at {}", fruit.id, reason.kind(), fruit.position);
+    }
+    commands.insert_resource(reason);
+    next_state.set(AppState::GameOver);
+  }
 }
 
 fn handle_inputs(
@@ -517,13 +1014,16 @@ fn handle_inputs(
   time: Res<Time>,
   mut controls_ui: Query<&mut Text, With<UIControls>>,
   windows: Query<&Window>,
+  camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  mut next_pause: ResMut<NextState<PauseState>>,
 ) {
   
   // flip touch direction when window switches views
-  let window = windows.single();
   let mut flip_touch_dir = false;
-  if window.height() > window.width() * 1.2 {
-    flip_touch_dir = true;
+  if let Ok(window) = windows.get_single() {
+    if window.height() > window.width() * 1.2 {
+      flip_touch_dir = true;
+    }
   }
 
   match controls.get_single_mut() {
@@ -532,15 +1032,16 @@ fn handle_inputs(
       if cooldown.timer.just_finished() {
         controls.drop_lock = false;
       }
-      if keys.pressed(KeyCode::Q) || keys.pressed(KeyCode::Escape) {
-        controls.end_game = true;
+      if keys.just_pressed(KeyCode::Escape) || keys.just_pressed(KeyCode::P) || keys.just_pressed(KeyCode::Q) {
+        next_pause.set(PauseState::Paused);
       }
       if !controls.drop_lock && (keys.just_pressed(KeyCode::Space) || keys.just_pressed(KeyCode::Return)) {
-        controls.drop = true;
+        controls.drop_queued = true;
         controls.drop_lock = true;
         cooldown.timer.reset();
-      } else {
-        controls.drop = false;
+      }
+      if keys.just_pressed(KeyCode::C) || keys.just_pressed(KeyCode::ShiftLeft) {
+        controls.hold_queued = true;
       }
       let mut move_dir = 0.0;
       if keys.pressed(KeyCode::Left) || keys.pressed(KeyCode::A) {
@@ -553,16 +1054,35 @@ fn handle_inputs(
 
       // touch events
       for touch in touch_events.iter() {
+        // pause button, the touch is not tracked so its release won't drop
+        if touch.phase == TouchPhase::Started && touch_to_world(&camera, touch.position)
+          .is_some_and(|pos| pos.distance(PAUSE_BUTTON_POS.truncate()) < 30.0) {
+          next_pause.set(PauseState::Paused);
+          break;
+        }
         // start tracking newest touch
         if touch.phase == TouchPhase::Started {
           controls.touch_id = touch.id;
           controls.touch_start = touch.position;
           break;
         }
+        // swipe up to hold, up is +x on screen when the camera is rotated
+        if touch.phase == TouchPhase::Ended && touch.id == controls.touch_id {
+          let swipe_up = if flip_touch_dir {
+            touch.position.x - controls.touch_start.x
+          } else {
+            controls.touch_start.y - touch.position.y
+          };
+          if swipe_up > 80.0 {
+            controls.move_dir = 0.0;
+            controls.hold_queued = true;
+            continue;
+          }
+        }
         // drop on touch release
         if touch.phase == TouchPhase::Ended && touch.id == controls.touch_id && !controls.drop_lock {
           controls.move_dir = 0.0;
-          controls.drop = true;
+          controls.drop_queued = true;
           controls.drop_lock = true;
           cooldown.timer.reset();
         }
@@ -598,134 +1118,155 @@ fn handle_inputs(
   
 }
 
+fn pause_on_focus_lost(
+  mut focus_events: EventReader<WindowFocused>,
+  mut next_pause: ResMut<NextState<PauseState>>,
+) {
+  if focus_events.iter().any(|e| !e.focused) {
+    next_pause.set(PauseState::Paused);
+  }
+}
+
+fn stop_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+  rapier_config.physics_pipeline_active = false;
+}
+
+fn resume_game(
+  state: Res<State<AppState>>,
+  mut rapier_config: ResMut<RapierConfiguration>,
+  mut controls: Query<(&mut Controls, &mut CoolDown)>,
+) {
+  // quitting from the pause menu also leaves Paused, keep physics off then
+  if *state.get() != AppState::InGame {
+    return;
+  }
+  rapier_config.physics_pipeline_active = true;
+  // the tap or key that resumed shouldn't also drop
+  if let Ok((mut controls, mut cooldown)) = controls.get_single_mut() {
+    controls.drop_lock = true;
+    controls.drop_queued = false;
+    cooldown.timer.reset();
+  }
+}
+
+// hold a drop until the next fixed tick picks it up, for every board's controls
+pub(crate) fn latch_inputs(mut controls: Query<&mut Controls>) {
+  for mut controls in controls.iter_mut() {
+    controls.drop = std::mem::take(&mut controls.drop_queued);
+    controls.hold = std::mem::take(&mut controls.hold_queued);
+  }
+}
+
 fn handle_active_fruit(
   mut commands: Commands,
   positions: Res<Positions>,
+  fixed_time: Res<FixedTime>,
   controls: Query<(&Controls, &CoolDown)>,
   mut meshes: ResMut<Assets<Mesh>>,
   mut materials: ResMut<Assets<ColorMaterial>>,
   mut active_fruit_q: Query<(Entity, &mut Transform, &ActiveFruit), With<ActiveFruit>>,
-  next_fruit_q: Query<&NextFruit>,
+  mut queue: ResMut<FruitQueue>,
+  mut hold: ResMut<HoldSlot>,
+  mut rng: ResMut<GameRng>,
+  table: Res<FruitTable>,
+  settings: Res<Settings>,
+  speed_override: Option<Res<MoveSpeedOverride>>,
+  mut dropped: EventWriter<FruitDropped>,
 ) {
   let (input, _cooldown) = controls.single();
+  let move_speed = speed_override.map_or(settings.move_speed, |s| s.0);
 
-  // get active fruit
-  match active_fruit_q.get_single_mut() {
-    Ok((entity, transform, active_fruit)) => {
-      // spawn active fruit
-      if input.drop {
-        let cur_fruit = SUIKA[active_fruit.0 as usize];
-        let cur_translation = transform.into_inner().translation;
-        let cur_z = rand::thread_rng().gen_range(2.0..5.0);
-        let pos = Vec3::new(cur_translation.x, cur_translation.y, cur_z);
-        
-        // spawn collision fruit body
-        spawn_collider_fruit(&mut commands, &mut meshes,  &mut materials, cur_fruit, pos);
-
-        // despawn active fruit
-        commands.entity(entity).despawn_recursive();
-
-        // pick next fruit
-        let num: i32 = match next_fruit_q.get_single() {
-          Ok(next_fruit) => next_fruit.0,
-          Err(_) => rand::thread_rng().gen_range(0..4)
-        };
-        let active_fruit = SUIKA[num as usize];
-        spawn_active_fruit(&mut commands, &positions, &mut meshes, &mut materials, active_fruit, cur_translation.x);
-
-        // prevent further active control
-        return;
-      }
-      
-      // calculations for updating active fruit
-      let new_x = transform.clone().translation.x + MOVE_SPEED * input.move_dir;
-      let suika_num = active_fruit.0;
-      let limit = positions.cup_right_x - SUIKA[suika_num as usize].size / 2.0;
-      // update active fruit render
-      if new_x > -limit && new_x < limit {
-        transform.into_inner().translation.x = new_x;
-      } else if new_x >= limit {
-        transform.into_inner().translation.x = limit;
-      } else if new_x <= -limit {
-        transform.into_inner().translation.x = -limit;
-      }
-    },
-    Err(_e) => {
-      // pick new fruit
-      let num: i32 = match next_fruit_q.get_single() {
-        Ok(next_fruit) => next_fruit.0,
-        Err(_) => rand::thread_rng().gen_range(0..3)
-      };
-      let active_fruit = SUIKA[num as usize];
-      spawn_active_fruit(&mut commands, &positions, &mut meshes, &mut materials, active_fruit, 0.0);
-
-    }
+  let drop = control_active_fruit(
+    &mut commands, &mut meshes, &mut materials, &table, &positions,
+    queue.reborrow(), hold.reborrow(), rng.reborrow(),
+    input, active_fruit_q.get_single_mut().ok(), move_speed * fixed_time.period.as_secs_f32(), None,
+  );
+  if let Some(drop) = drop {
+    dropped.send(drop);
   }
 }
 
+// redraws the preview column whenever the queue changes
 fn handle_next_fruit(
   mut commands: Commands,
-  controls: Query<&Controls>,
   mut meshes: ResMut<Assets<Mesh>>,
   mut materials: ResMut<Assets<ColorMaterial>>,
-  mut next_fruit_q: Query<Entity, With<NextFruit>>,
+  next_fruit_q: Query<Entity, With<NextFruit>>,
+  queue: Res<FruitQueue>,
+  table: Res<FruitTable>,
 ) {
-  let input = controls.single();
-  // spawn next fruit if not exist
-  match next_fruit_q.get_single_mut() {
-    Ok(entity) => {
-      if input.drop {
-        // despawn NextFruit
-        commands.entity(entity).despawn_recursive();
-        // spawn new NextFruit
-        // pick random fruit
-        let num: i32 = rand::thread_rng().gen_range(0..5);
-        let next_fruit = SUIKA[num as usize];
-        spawn_next_fruit(&mut commands, &mut meshes, &mut materials, next_fruit);
-      }
-    },
-    Err(_) => {
-      // pick random fruit
-      let num: i32 = rand::thread_rng().gen_range(0..5);
-      let next_fruit = SUIKA[num as usize];
-      spawn_next_fruit(&mut commands, &mut meshes, &mut materials, next_fruit);
-    }
+  if !queue.is_changed() {
+    return;
+  }
+  for entity in next_fruit_q.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+  for (slot, id) in queue.fruits.iter().enumerate() {
+    spawn_next_fruit(&mut commands, &mut meshes, &mut materials, &table, *id, slot);
   }
 }
 
-fn handle_merging(
+fn handle_held_fruit(
   mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  held_fruit_q: Query<Entity, With<HeldFruit>>,
+  hold: Res<HoldSlot>,
+  table: Res<FruitTable>,
+) {
+  if !hold.is_changed() {
+    return;
+  }
+  for entity in held_fruit_q.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+  if let Some(id) = hold.fruit {
+    spawn_held_fruit(&mut commands, &mut meshes, &mut materials, &table, id);
+  }
+}
+
+fn buffer_collisions(
   mut collisions: EventReader<CollisionEvent>,
+  mut pending: ResMut<PendingCollisions>,
+) {
+  pending.0.extend(collisions.iter().cloned());
+}
+
+fn handle_merging(
+  mut commands: Commands,
+  mut collisions: ResMut<PendingCollisions>,
   mut meshes: ResMut<Assets<Mesh>>,
   mut materials: ResMut<Assets<ColorMaterial>>,
-  fruits: Query<(Entity, &Fruit, &Transform)>,
+  fruits: MergeFruits,
   mut score: ResMut<Score>,
+  mut chain: ResMut<Chain>,
+  mut rng: ResMut<GameRng>,
+  table: Res<FruitTable>,
+  fixed_time: Res<FixedTime>,
+  mut merged: EventWriter<FruitMerged>,
 ) {
-  for collision in collisions.iter() {
-    if let CollisionEvent::Started(collider_a, collider_b, _) = collision {
-      // get fruits from collision, if it was a collision between fruits
-      if let Ok([fruit_a, fruit_b]) = fruits.get_many([*collider_a, *collider_b]) {
-        if fruit_a.1.size == fruit_b.1.size && fruit_a.1.id < 10 {
-          // calculate midpoint between 2 fruits
-          let new_translation = Vec3::new(
-            (fruit_a.2.translation.x + fruit_b.2.translation.x) / 2.0,
-            (fruit_a.2.translation.y + fruit_b.2.translation.y) / 2.0,
-            rand::thread_rng().gen_range(2.0..5.0)
-          );
-          let new_fruit = SUIKA[(fruit_a.1.id + 1) as usize];
-          // remove collided fruits
-          commands.entity(fruit_a.0).despawn_recursive();
-          commands.entity(fruit_b.0).despawn_recursive();
-          // spawn new fruit from SUIKA + 1
-          spawn_collider_fruit(&mut commands,  &mut meshes, &mut materials, new_fruit, new_translation);
-          // add points
-          score.0 += new_fruit.score;
-          // exit for loop - only calculate one successful merge per frame
-          break;
-        }
-        
-      }
-    }
+  chain.since_merge += fixed_time.period.as_secs_f32();
+
+  for pair in take_merges(&mut collisions, &fruits, &table) {
+    let merge = merge_fruits(&mut commands, &mut meshes, &mut materials, &table, pair, &mut chain, &mut rng);
+    score.0 += merge.score;
+    merged.send(merge);
+  }
+}
+
+fn track_game_stats(
+  fixed_time: Res<FixedTime>,
+  mut stats: ResMut<GameStats>,
+  mut dropped: EventReader<FruitDropped>,
+  mut merged: EventReader<FruitMerged>,
+) {
+  stats.duration += fixed_time.period.as_secs_f32();
+  for drop in dropped.iter() {
+    stats.drops += 1;
+    stats.highest_tier = stats.highest_tier.max(drop.id);
+  }
+  for merge in merged.iter() {
+    stats.highest_tier = stats.highest_tier.max(merge.output_id);
   }
 }
 
@@ -739,61 +1280,373 @@ fn update_score(
   }
 }
 
+fn update_clock(
+  mut clock_q: Query<&mut Text, With<UIClock>>,
+  mode: Res<GameMode>,
+  stats: Res<GameStats>,
+) {
+  let Some(limit) = mode.time_limit() else {
+    return;
+  };
+  if let Ok(mut text) = clock_q.get_single_mut() {
+    // counts 3:00 down to 0:01, then the game ends
+    text.sections[0].value = format!("Time {}", format_duration((limit - stats.duration).ceil()));
+  }
+}
+
+fn update_chain(
+  mut chain_q: Query<&mut Text, With<UIChain>>,
+  chain: Res<Chain>,
+) {
+  if let Ok(mut text) = chain_q.get_single_mut() {
+    text.sections[0].value = if chain.is_active() {
+      format!("Chain x{}", chain.multiplier())
+    } else {
+      String::new()
+    };
+  }
+}
+
+fn scale_ui(
+  settings: Res<Settings>,
+  mut ui_elements: Query<&mut Transform, With<PermUIComponent>>,
+) {
+  for mut transform in ui_elements.iter_mut() {
+    transform.scale = Vec3::new(settings.ui_scale, settings.ui_scale, 1.0);
+  }
+}
+
+// shows the table of the selected mode
+fn update_high_score_list<T: Leaderboard>(
+  highscore: Option<Res<Persistent<T>>>,
+  mode: Res<GameMode>,
+  mut highscore_title: Query<&mut Text, (With<UIHighScoreTitle>, Without<UIHighScoreList>)>,
+  mut highscore_list: Query<(&mut Text, &UIHighScoreList)>,
+) {
+  if !mode.is_changed() && !highscore.as_ref().is_some_and(|h| h.is_changed()) {
+    return;
+  }
+  for mut text in highscore_title.iter_mut() {
+    text.sections[0].value = match *mode {
+      GameMode::Classic => "High Scores:".to_string(),
+      _ => format!("{}:", mode.name()),
+    };
+  }
+  let entries = highscore.as_ref().map(|h| h.table().entries.as_slice()).unwrap_or_default();
+  for (mut text, hs_list) in highscore_list.iter_mut() {
+    let (title, details) = high_score_row(entries.get(hs_list.0), hs_list.0);
+    text.sections[0].value = title;
+    text.sections[1].value = details;
+  }
+}
+
+// zen games, puzzles and versus matches aren't ranked
+fn clear_high_score_list(
+  mode: Res<GameMode>,
+  mut highscore_title: Query<&mut Text, (With<UIHighScoreTitle>, Without<UIHighScoreList>)>,
+  mut highscore_list: Query<&mut Text, With<UIHighScoreList>>,
+) {
+  for mut text in highscore_title.iter_mut() {
+    text.sections[0].value = format!("{}: no scores kept", mode.name());
+  }
+  for mut text in highscore_list.iter_mut() {
+    text.sections[0].value = String::new();
+    text.sections[1].value = String::new();
+  }
+}
+
+// adds merges and finished games to the persisted totals, if there are any
+fn record_lifetime_stats(
+  lifetime: Option<ResMut<Persistent<LifetimeStats>>>,
+  table: Res<FruitTable>,
+  mut merged: EventReader<FruitMerged>,
+  mut game_over: EventReader<GameOver>,
+) {
+  let Some(mut lifetime) = lifetime else {
+    return;
+  };
+  for merge in merged.iter() {
+    lifetime.add_merge(merge.input_id);
+    if merge.output_id == table.top_id() {
+      lifetime.top_fruits += 1;
+    }
+  }
+  for game_over in game_over.iter() {
+    lifetime.games_played += 1;
+    lifetime.total_score += game_over.score as i64;
+    lifetime.total_drops += game_over.stats.drops as u64;
+    lifetime.playtime += game_over.stats.duration as f64;
+    *lifetime.game_overs.entry(game_over.reason.kind().to_string()).or_default() += 1;
+    lifetime.persist().ok();
+  }
+}
+
+fn announce_game_over(
+  reason: Res<GameOverReason>,
+  score: Res<Score>,
+  stats: Res<GameStats>,
+  mut game_over: EventWriter<GameOver>,
+) {
+  game_over.send(GameOver { reason: *reason, score: score.0, player: None, stats: *stats });
+}
+
 fn pause_state(
   mut commands: Commands,
   controls: Query<Entity, With<Controls>>,
   active_fruit: Query<Entity, With<ActiveFruit>>,
   next_fruit: Query<Entity, With<NextFruit>>,
+  held_fruit: Query<Entity, With<HeldFruit>>,
+  mut hold: ResMut<HoldSlot>,
   ui_elements: Query<Entity, With<UIComponent>>,
   mut score: ResMut<Score>,
-  mut highscore: ResMut<Persistent<HighScore>>,
+  mode: Res<GameMode>,
   mut rapier_config: ResMut<RapierConfiguration>,
-  mut highscore_list: Query<(&mut Text, &UIHighScoreList)>,
 ) {
   // destroy components that should only have 1 existence
   commands.entity(controls.single()).despawn_recursive();
-  commands.entity(active_fruit.single()).despawn_recursive();
-  commands.entity(next_fruit.single()).despawn_recursive();
+  // puzzles can end with no fruit left to drop
+  for e in active_fruit.iter() {
+    commands.entity(e).despawn_recursive();
+  }
+  for e in next_fruit.iter() {
+    commands.entity(e).despawn_recursive();
+  }
+  for e in held_fruit.iter() {
+    commands.entity(e).despawn_recursive();
+  }
+  *hold = HoldSlot::default();
 
   // destroy ui elements only shown during gameplay
   for e in ui_elements.iter() {
     commands.entity(e).despawn_recursive();
   }
 
-  // calculate best score:
-  if score.0 > score.1 {
+  // session best is the classic one, other modes keep their own tables
+  if *mode == GameMode::Classic && score.0 > score.1 {
     score.1 = score.0;
   }
-  // update persistent high score
-  let mut temp_score = score.0;
-  for hscore in highscore.0.iter_mut() {
-    if temp_score > *hscore {
-      let temp = temp_score;
-      temp_score = *hscore;
-      *hscore = temp;
+  // pause physics
+  rapier_config.physics_pipeline_active = false;
+}
+
+// puts the game into the mode's table (headless apps run without one). The game
+// over screen asks for a name, until then it goes in as the last one used
+fn record_high_score<T: Leaderboard>(
+  mut commands: Commands,
+  score: Res<Score>,
+  stats: Res<GameStats>,
+  highscore: Option<ResMut<Persistent<T>>>,
+) {
+  let Some(mut highscore) = highscore else {
+    return;
+  };
+  let table = highscore.table_mut();
+  let entry = ScoreEntry {
+    score: score.0,
+    name: table.last_name.clone(),
+    timestamp: now_timestamp(),
+    duration: stats.duration,
+    drops: stats.drops,
+    highest_tier: stats.highest_tier,
+  };
+  if let Some(rank) = table.insert(entry) {
+    commands.insert_resource(NewHighScore(rank));
+    highscore.persist().ok();
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+// moves, swaps or drops one cup's active fruit and hands out the next, for handle_active_fruit
+// and each versus board. `step` is a full move this tick, fruits in `player`'s cup get OnBoard
+pub(crate) fn control_active_fruit(
+  commands: &mut Commands,
+  meshes: &mut ResMut<Assets<Mesh>>,
+  materials: &mut ResMut<Assets<ColorMaterial>>,
+  table: &FruitTable,
+  positions: &Positions,
+  mut queue: impl DerefMut<Target = FruitQueue>,
+  mut hold: impl DerefMut<Target = HoldSlot>,
+  mut rng: impl DerefMut<Target = GameRng>,
+  input: &Controls,
+  active: Option<(Entity, Mut<Transform>, &ActiveFruit)>,
+  step: f32,
+  player: Option<usize>,
+) -> Option<FruitDropped> {
+  let Some((entity, mut transform, active_fruit)) = active else {
+    // pick new fruit
+    if let Some(num) = queue.try_pop(&mut rng, table).or_else(|| hold.fruit.take()) {
+      let center_x = 0.5 * (positions.cup_left_x + positions.cup_right_x);
+      let e = spawn_active_fruit(commands, positions, meshes, materials, table, num, center_x);
+      put_on_board(commands, e, player);
     }
+    return None;
+  };
+  let cur_x = transform.translation.x;
+
+  if input.drop {
+    let pos = Vec3::new(cur_x, transform.translation.y, rng.gen_range(2.0..5.0));
+    // spawn collision fruit body
+    let e = spawn_collider_fruit(commands, meshes, materials, table, active_fruit.0, pos);
+    put_on_board(commands, e, player);
+    commands.entity(entity).despawn_recursive();
+
+    // pick next fruit, the held one is the last left once a fixed sequence runs out
+    if let Some(num) = queue.try_pop(&mut rng, table).or_else(|| hold.fruit.take()) {
+      let next = spawn_active_fruit(commands, positions, meshes, materials, table, num, cur_x);
+      put_on_board(commands, next, player);
+    }
+    hold.swapped = false;
+    return Some(FruitDropped { entity: e, id: active_fruit.0, x: pos.x, player });
   }
-  highscore.persist().ok();
 
-  // update highscore list
-  for (text, hs_list) in highscore_list.iter_mut() {
-    text.into_inner().sections[0].value = highscore.0[hs_list.0].to_string();
+  // swap with the held fruit, or the next one if nothing is held yet
+  // (nothing to swap in once a fixed sequence has run out)
+  if input.hold && !hold.swapped {
+    if let Some(num) = hold.fruit.or_else(|| queue.try_pop(&mut rng, table)) {
+      commands.entity(entity).despawn_recursive();
+      hold.fruit = Some(active_fruit.0);
+      let e = spawn_active_fruit(commands, positions, meshes, materials, table, num, cur_x);
+      put_on_board(commands, e, player);
+      hold.swapped = true;
+      return None;
+    }
   }
 
-  // pause physics
-  rapier_config.physics_pipeline_active = false;
+  // keep the whole fruit over the cup
+  let r = table.get(active_fruit.0).size / 2.0;
+  let new_x = cur_x + step * input.move_dir;
+  transform.translation.x = new_x.clamp(positions.cup_left_x + r, positions.cup_right_x - r);
+  None
 }
 
-// --- HELPER FUNCTIONS ---
-fn spawn_active_fruit(
+// fruits that can merge, handle_merging's and the versus boards'
+pub(crate) type MergeFruits<'w, 's> = Query<'w, 's, (Entity, &'static Fruit, &'static Transform, Option<&'static ChainLink>, Option<&'static OnBoard>)>;
+pub(crate) type MergeFruit<'a> = (Entity, &'a Fruit, &'a Transform, Option<&'a ChainLink>, Option<&'a OnBoard>);
+
+// pairs of same tier fruits that started touching, one per cup a tick. Rapier won't start
+// touching fruits again, so the other pairs wait for the next tick
+pub(crate) fn take_merges<'a>(
+  collisions: &mut PendingCollisions,
+  fruits: &'a MergeFruits,
+  table: &FruitTable,
+) -> Vec<[MergeFruit<'a>; 2]> {
+  let mut merges: Vec<[MergeFruit; 2]> = Vec::new();
+  collisions.0.retain(|collision| {
+    let CollisionEvent::Started(collider_a, collider_b, _) = collision else {
+      return false;
+    };
+    // only collisions between fruits of one cup
+    let Ok([fruit_a, fruit_b]) = fruits.get_many([*collider_a, *collider_b]) else {
+      return false;
+    };
+    let cup = fruit_a.4.map(|b| b.0);
+    if fruit_a.1.id != fruit_b.1.id || fruit_b.4.map(|b| b.0) != cup || fruit_a.1.id >= table.top_id() {
+      return false;
+    }
+    if merges.iter().any(|[a, _]| a.4.map(|b| b.0) == cup) {
+      return true;
+    }
+    merges.push([fruit_a, fruit_b]);
+    false
+  });
+  merges
+}
+
+// replaces a pair from take_merges with a fruit of the next tier at their midpoint,
+// extending `chain`. The event has the points it's worth
+pub(crate) fn merge_fruits(
+  commands: &mut Commands,
+  meshes: &mut ResMut<Assets<Mesh>>,
+  materials: &mut ResMut<Assets<ColorMaterial>>,
+  table: &FruitTable,
+  [fruit_a, fruit_b]: [MergeFruit; 2],
+  chain: &mut Chain,
+  rng: &mut GameRng,
+) -> FruitMerged {
+  let player = fruit_a.4.map(|b| b.0);
+  // calculate midpoint between 2 fruits
+  let new_translation = Vec3::new(
+    (fruit_a.2.translation.x + fruit_b.2.translation.x) / 2.0,
+    (fruit_a.2.translation.y + fruit_b.2.translation.y) / 2.0,
+    rng.gen_range(2.0..5.0)
+  );
+  let new_fruit = table.get(fruit_a.1.id + 1);
+
+  // extend the chain or start a new one
+  let linked = [fruit_a.3, fruit_b.3].iter()
+    .any(|link| link.is_some_and(|l| l.0 == chain.id));
+  chain.extend(linked);
+
+  // remove collided fruits
+  commands.entity(fruit_a.0).despawn_recursive();
+  commands.entity(fruit_b.0).despawn_recursive();
+  // spawn new fruit from next tier
+  let e = spawn_collider_fruit(commands, meshes, materials, table, new_fruit.id, new_translation);
+  commands.entity(e).insert(ChainLink(chain.id));
+  put_on_board(commands, e, player);
+  FruitMerged {
+    inputs: [fruit_a.0, fruit_b.0],
+    input_id: fruit_a.1.id,
+    output: e,
+    output_id: new_fruit.id,
+    position: new_translation.truncate(),
+    score: new_fruit.score * chain.multiplier(),
+    player,
+  }
+}
+
+// the rule a fruit breaks in the cup at `positions`. Past a wall ends the game at once,
+// MaxHeight only once fruits have rested over the line for LINE_TIMEOUT
+pub(crate) fn fruit_overflow(
+  positions: &Positions,
+  entity: Entity,
+  transform: &Transform,
+  velocity: &Velocity,
+  fruit: &Fruit,
+) -> Option<GameOverReason> {
+  let pos = transform.translation;
+  let offender = OffendingFruit { entity, id: fruit.id, position: pos.truncate() };
+  if pos.x > positions.cup_right_x + CONTAINER_T {
+    Some(GameOverReason::RightBoundary(offender))
+  } else if pos.x < positions.cup_left_x - CONTAINER_T {
+    Some(GameOverReason::LeftBoundary(offender))
+  } else if velocity.linvel.length() < MIN_SPEED && pos.y > positions.cup_max_y - (0.4 * fruit.size) {
+    Some(GameOverReason::MaxHeight(offender))
+  } else {
+    None
+  }
+}
+
+fn put_on_board(commands: &mut Commands, e: Entity, player: Option<usize>) {
+  if let Some(player) = player {
+    commands.entity(e).insert(OnBoard(player));
+  }
+}
+
+// "1. name  score" and "#tier  drops  time  date" of a high score panel row
+fn high_score_row(entry: Option<&ScoreEntry>, rank: usize) -> (String, String) {
+  let Some(entry) = entry else {
+    return (format!("{}. -\n", rank + 1), String::new());
+  };
+  let name = if entry.name.is_empty() { "???" } else { entry.name.as_str() };
+  (
+    format!("{}. {}  {}\n", rank + 1, name, entry.score),
+    format!("#{}  {} drops  {}  {}",
+      entry.highest_tier, entry.drops, format_duration(entry.duration), format_date(entry.timestamp)),
+  )
+}
+
+pub(crate) fn spawn_active_fruit(
   commands: &mut Commands,
   cup_pos: &Positions,
   meshes: &mut ResMut<Assets<Mesh>>,
   materials: &mut ResMut<Assets<ColorMaterial>>,
-  fruit: Fruit,
+  table: &FruitTable,
+  id: i32,
   x_pos: f32,
-) {
-  let active_fruit_y = cup_pos.cup_max_y + SUIKA[5].size / 2.0;
+) -> Entity {
+  let fruit = table.get(id);
+  let active_fruit_y = cup_pos.cup_max_y + table.get(table.largest_spawn_id() + 1).size / 2.0;
   let preview_bar_y = cup_pos.cup_base_y;
   let preview_bar_h = active_fruit_y - cup_pos.cup_base_y;
 
@@ -801,18 +1654,18 @@ fn spawn_active_fruit(
     ActiveFruit(fruit.id),
     MaterialMesh2dBundle {
       mesh: meshes.add(shape::Circle::new(fruit.size / 2.0).into()).into(),
-      material: materials.add(ColorMaterial::from(fruit.color)),
+      material: materials.add(table.material(id)),
       transform: Transform::from_translation(Vec3::new(x_pos, active_fruit_y, 1.0)),
       ..default()
     },
   )).with_children(|root| {
     // spawn preview bar
-    root.spawn(MaterialMesh2dBundle {
+    root.spawn((PreviewBar, MaterialMesh2dBundle {
       mesh: meshes.add(shape::Quad::new(Vec2::new(1.5, preview_bar_h)).into()).into(),
       material: materials.add(ColorMaterial::from(Color::WHITE)),
       transform: Transform::from_translation(Vec3::new(0.0, preview_bar_y, -1.0)),
       ..default()
-    });
+    }));
     // spawn text
     root.spawn(Text2dBundle {
       text: Text::from_section(
@@ -826,21 +1679,65 @@ fn spawn_active_fruit(
       transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
       ..default()
     });
-  });
+  }).id()
 }
 
-fn spawn_next_fruit(
+pub(crate) fn spawn_next_fruit(
   commands: &mut Commands,
   meshes: &mut ResMut<Assets<Mesh>>,
   materials: &mut ResMut<Assets<ColorMaterial>>,
-  fruit: Fruit,
+  table: &FruitTable,
+  id: i32,
+  slot: usize,
 ) {
+  let fruit = table.get(id);
+  // front of the queue in the hold area, the rest shrunk into a column beside it
+  let (radius, position, font_size) = match slot {
+    0 => (fruit.size / 2.0, HOLD_POS_FRUIT, 20.0),
+    _ => (
+      f32::min(fruit.size / 2.0, 16.0),
+      QUEUE_POS - Vec3::new(0.0, 40.0 * (slot - 1) as f32, 0.0),
+      14.0,
+    ),
+  };
   commands.spawn((
     NextFruit(fruit.id),
     MaterialMesh2dBundle {
-      mesh: meshes.add(shape::Circle::new(fruit.size / 2.0).into()).into(),
-      material: materials.add(ColorMaterial::from(fruit.color)),
-      transform: Transform::from_translation(HOLD_POS_FRUIT),
+      mesh: meshes.add(shape::Circle::new(radius).into()).into(),
+      material: materials.add(table.material(id)),
+      transform: Transform::from_translation(position),
+      ..default()
+    }
+  )).with_children(|root| {
+    root.spawn(Text2dBundle {
+      text: Text::from_section(
+        fruit.id.to_string(),
+        TextStyle {
+          font_size, 
+        color: TEXT_COLOR,
+          ..default()
+        }
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+      ..default()
+    });
+  });
+}
+
+fn spawn_held_fruit(
+  commands: &mut Commands,
+  meshes: &mut ResMut<Assets<Mesh>>,
+  materials: &mut ResMut<Assets<ColorMaterial>>,
+  table: &FruitTable,
+  id: i32,
+) {
+  let fruit = table.get(id);
+  commands.spawn((
+    HeldFruit(fruit.id),
+    MaterialMesh2dBundle {
+      mesh: meshes.add(shape::Circle::new(f32::min(fruit.size / 2.0, 40.0)).into()).into(),
+      material: materials.add(table.material(id)),
+      transform: Transform::from_translation(SWAP_POS_FRUIT),
       ..default()
     }
   )).with_children(|root| {
@@ -859,13 +1756,15 @@ fn spawn_next_fruit(
   });
 }
 
-fn spawn_collider_fruit(
+pub(crate) fn spawn_collider_fruit(
   commands: &mut Commands,
   meshes: &mut ResMut<Assets<Mesh>>,
   materials: &mut ResMut<Assets<ColorMaterial>>,
-  cur_fruit: Fruit,
+  table: &FruitTable,
+  id: i32,
   position: Vec3,
-) {
+) -> Entity {
+  let cur_fruit = table.get(id);
 
   let angular_vel = (position.z - 3.5) * 0.2;
 
@@ -882,7 +1781,7 @@ fn spawn_collider_fruit(
     ActiveEvents::COLLISION_EVENTS,
     MaterialMesh2dBundle {
       mesh: meshes.add(shape::Circle::new(cur_fruit.size / 2.0).into()).into(),
-      material: materials.add(ColorMaterial::from(cur_fruit.color)),
+      material: materials.add(table.material(id)),
       transform: Transform::from_translation(position),
       ..default()
     },
@@ -899,5 +1798,5 @@ fn spawn_collider_fruit(
       transform: Transform::from_translation(Vec3::new(0.0, 0.0, 2.0)),
       ..default()
     });
-  });
+  }).id()
 }
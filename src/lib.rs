@@ -0,0 +1,100 @@
+//! Suika game clone on bevy + rapier.
+//!
+//! `SuikaPlugins` adds the full game (cup, menus, camera, fixed-step physics) to an app
+//! with `DefaultPlugins`. Each plugin can also be added on its own, e.g. without
+//! `MenuPlugin` to embed just the cup, or on `MinimalPlugins` via `sim::headless_app`.
+//! The game expects `Persistent<HighScore>` and `Persistent<LifetimeStats>` resources if
+//! high scores and statistics should be saved, and `SavePlugin` needs a
+//! `Persistent<SavedGame>` slot. `SettingsPlugin` writes the settings screen back to a
+//! `Persistent<Settings>` when there is one. `AutoPlayerPlugin` is optional and lets a
+//! bot take over the controls.
+
+// bevy systems routinely take many params
+#![allow(clippy::too_many_arguments)]
+
+use bevy::{prelude::*, app::PluginGroupBuilder};
+
+pub mod util;
+pub mod camera;
+pub mod menu;
+pub mod game;
+pub mod sim;
+pub mod replay;
+pub mod save;
+pub mod daily;
+pub mod puzzle;
+pub mod versus;
+pub mod settings;
+pub mod autoplay;
+pub mod env;
+
+pub use util::{
+  AppState,
+  PauseState,
+  Score,
+  HighScore,
+  TimeAttackHighScore,
+  DailyHighScore,
+  DailyRun,
+  DailyProgress,
+  Leaderboard,
+  GameMode,
+  ScoreEntry,
+  NewHighScore,
+  LifetimeStats,
+  GameSeed,
+  GameRng,
+  GameOverReason,
+  OffendingFruit,
+  Fruit,
+  FruitTable,
+  CoolDown,
+  MainCamera,
+};
+pub use camera::CameraPlugin;
+pub use menu::{MenuPlugin, MenuItem};
+pub use game::{
+  InGamePlugin,
+  FixedPhysicsPlugin,
+  GameSet,
+  Positions,
+  Controls,
+  Cup,
+  ActiveFruit,
+  NextFruit,
+  FruitQueue,
+  HoldSlot,
+  HeldFruit,
+  Chain,
+  ChainLink,
+  OnBoard,
+  GameStats,
+  FruitDropped,
+  FruitMerged,
+  GameOver,
+  PreviewBar,
+  Timeout,
+};
+pub use replay::{ReplayPlugin, Replay, ReplayMode, ControlFrame};
+pub use save::{SavePlugin, SavedGame, FruitState, ResumeGame};
+pub use daily::DailyPlugin;
+pub use puzzle::{PuzzlePlugin, Levels, Level, LevelFruit, Goal, CurrentLevel, PuzzleProgress};
+pub use versus::{VersusPlugin, VersusBindings, PlayerBindings, VersusMatch, VersusBoard};
+pub use settings::{SettingsPlugin, Settings, MoveSpeedOverride};
+pub use autoplay::{AutoPlayerPlugin, AutoPlayer, Strategy};
+pub use env::{SuikaEnv, Observation, FruitObservation, StepResult};
+
+pub struct SuikaPlugins;
+
+impl PluginGroup for SuikaPlugins {
+  fn build(self) -> PluginGroupBuilder {
+    PluginGroupBuilder::start::<Self>()
+      .add(FixedPhysicsPlugin)
+      .add(CameraPlugin)
+      .add(InGamePlugin)
+      .add(MenuPlugin)
+      .add(PuzzlePlugin)
+      .add(VersusPlugin)
+      .add(SettingsPlugin)
+  }
+}
@@ -2,27 +2,68 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
 use bevy_persistent::prelude::*;
 
-#[cfg(debug_assertions)]
-use bevy::input::touch::TouchPhase;
-
-mod util;
-use util::{AppState, Score, BG_COLOR, SCREEN_H, SCREEN_W, MainCamera, HighScore};
-
-mod menu;
-use menu::MenuPlugin;
-
-mod game;
-use game::InGamePlugin;
+use suika_clone::{SuikaPlugins, ReplayPlugin, AutoPlayerPlugin, AutoPlayer, Strategy, Replay, ReplayMode, SavePlugin, DailyPlugin, SavedGame, HighScore, TimeAttackHighScore, DailyHighScore, DailyProgress, PuzzleProgress, LifetimeStats, GameSeed, Settings};
+use suika_clone::util::{SCREEN_H, SCREEN_W};
 
 fn main() {
 
 	// set persistent save location
 	let mut persistent_path = "./save.bin";
+	let mut replay_path = "./replay.bin";
+	let mut game_path = "./game.bin";
+	let mut settings_path = "./settings.ini";
+	let mut stats_path = "./stats.bin";
+	let mut time_attack_path = "./time_attack.bin";
+	let mut daily_path = "./daily.bin";
+	let mut daily_progress_path = "./daily_progress.bin";
+	let mut puzzle_path = "./puzzles.bin";
 	if cfg!(target_arch = "wasm32") {
 		persistent_path = "local/save.bin";
+		replay_path = "local/replay.bin";
+		game_path = "local/game.bin";
+		settings_path = "local/settings.ini";
+		stats_path = "local/stats.bin";
+		time_attack_path = "local/time_attack.bin";
+		daily_path = "local/daily.bin";
+		daily_progress_path = "local/daily_progress.bin";
+		puzzle_path = "local/puzzles.bin";
+	}
+
+	let settings = Persistent::<Settings>::builder()
+		.name("settings")
+		.format(StorageFormat::Ini)
+		.path(settings_path)
+		.default(Settings::default())
+		.revertible(true)
+		.revert_to_default_on_deserialization_errors(true)
+		.build()
+		.expect("Err: Could not load settings");
+	let game_settings = settings.get().clone();
+
+	// optional fixed seed to reproduce a game: `suika-clone --seed 1234`
+	let mut seed = arg_value("--seed").and_then(|s| s.parse().ok());
+
+	// play back a recorded game instead of recording: `suika-clone --replay replay.bin`
+	let playback_path = arg_value("--replay");
+	let replay_mode = if playback_path.is_some() {
+		ReplayMode::Playback
+	} else {
+		ReplayMode::Record
+	};
+	let replay = Persistent::<Replay>::builder()
+		.name("replay")
+		.format(StorageFormat::Bincode)
+		.path(playback_path.unwrap_or(replay_path.to_string()))
+		.default(Replay::default())
+		// an old recording is simply replaced, but never throw away a file given to --replay
+		.revertible(true)
+		.revert_to_default_on_deserialization_errors(replay_mode == ReplayMode::Record)
+		.build()
+		.expect("Err: Could not load replay");
+	if replay_mode == ReplayMode::Playback {
+		seed = Some(replay.seed);
 	}
 
 	App::new()
@@ -37,91 +78,127 @@ fn main() {
 			..default()
 		}))
 		.add_plugins((
-			RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
-			// RapierDebugRenderPlugin::default(),
+			SuikaPlugins,
+			ReplayPlugin,
+			SavePlugin,
+			DailyPlugin,
+			AutoPlayerPlugin,
+			// bevy_rapier2d::prelude::RapierDebugRenderPlugin::default(),
 		))
-		.insert_resource(ClearColor(BG_COLOR))
-		.insert_resource(Score(0, 0))
-		.insert_resource(Persistent::<HighScore>::builder()
-			.name("high scores")
+		.insert_resource(GameSeed(seed))
+		.insert_resource(game_settings)
+		.insert_resource(settings)
+		.insert_resource(replay_mode)
+		.insert_resource(replay)
+		// let the bot play from the start: `suika-clone --autoplay`, add `--lookahead`
+		// for the searching one
+		.insert_resource(AutoPlayer::new(has_flag("--autoplay")).with_strategy(
+			if has_flag("--lookahead") { Strategy::Lookahead } else { Strategy::Heuristic }
+		))
+		.insert_resource(load_high_scores(persistent_path))
+		.insert_resource(Persistent::<TimeAttackHighScore>::builder()
+			.name("time attack high scores")
+			.format(StorageFormat::Bincode)
+			.path(time_attack_path)
+			.default(TimeAttackHighScore::default())
+			.revertible(true)
+			.revert_to_default_on_deserialization_errors(true)
+			.build()
+			.expect("Err: Could not load time attack high scores")
+		)
+		.insert_resource(Persistent::<DailyHighScore>::builder()
+			.name("daily high scores")
 			.format(StorageFormat::Bincode)
-			.path(persistent_path)
-			.default(HighScore([0,0,0,0,0,0,0,0]))
+			.path(daily_path)
+			.default(DailyHighScore::default())
+			.revertible(true)
+			.revert_to_default_on_deserialization_errors(true)
 			.build()
-			.expect("Err: Could not load high scores")
+			.expect("Err: Could not load daily high scores")
+		)
+		.insert_resource(Persistent::<DailyProgress>::builder()
+			.name("daily progress")
+			.format(StorageFormat::Bincode)
+			.path(daily_progress_path)
+			.default(DailyProgress::default())
+			.revertible(true)
+			.revert_to_default_on_deserialization_errors(true)
+			.build()
+			.expect("Err: Could not load daily progress")
+		)
+		.insert_resource(Persistent::<PuzzleProgress>::builder()
+			.name("puzzle progress")
+			.format(StorageFormat::Bincode)
+			.path(puzzle_path)
+			.default(PuzzleProgress::default())
+			.revertible(true)
+			.revert_to_default_on_deserialization_errors(true)
+			.build()
+			.expect("Err: Could not load puzzle progress")
+		)
+		.insert_resource(Persistent::<LifetimeStats>::builder()
+			.name("lifetime stats")
+			.format(StorageFormat::Bincode)
+			.path(stats_path)
+			.default(LifetimeStats::default())
+			.revertible(true)
+			.revert_to_default_on_deserialization_errors(true)
+			.build()
+			.expect("Err: Could not load statistics")
+		)
+		.insert_resource(Persistent::<SavedGame>::builder()
+			.name("saved game")
+			.format(StorageFormat::Bincode)
+			.path(game_path)
+			.default(SavedGame::default())
+			// an unreadable save (e.g. from an older version) just means nothing to continue
+			.revertible(true)
+			.revert_to_default_on_deserialization_errors(true)
+			.build()
+			.expect("Err: Could not load saved game")
 		)
-		.add_state::<AppState>()
-		.add_systems(Startup, initialize)
-		.add_systems(Update, zoom_camera)
-		.add_systems(Update, mock_touch)
-		.add_plugins(InGamePlugin)
-		.add_plugins(MenuPlugin)
 		.run();
 }
 
-fn initialize(mut commands: Commands) {
-	// spawn camera
-  commands.spawn((Camera2dBundle::default(), MainCamera));
-}
+// save.bin from before named entries holds a bare [i32; 8], carry those scores over
+fn load_high_scores(path: &str) -> Persistent<HighScore> {
+	let builder = || Persistent::<HighScore>::builder()
+		.name("high scores")
+		.format(StorageFormat::Bincode)
+		.path(path)
+		.default(HighScore::default());
+	if let Ok(highscore) = builder().build() {
+		return highscore;
+	}
 
-fn zoom_camera(
-	windows: Query<&Window>,
-	mut camera: Query<&mut Transform, With<MainCamera>>,
-) {
-	// note: only works if there's a single camera + single window
-	for mut transform in camera.iter_mut() {
-		let window = windows.single();
+	#[cfg(not(target_arch = "wasm32"))]
+	let legacy = std::fs::read(path).ok().and_then(|bytes| HighScore::from_legacy(&bytes));
+	#[cfg(target_arch = "wasm32")]
+	let legacy: Option<HighScore> = None;
 
-		// rotate camera 90 deg if window h > window w
-		if window.height() > window.width() * 1.1 {
-			let delta_x = SCREEN_H / window.width();
-			let delta_y = SCREEN_W / window.height();
-			let delta = if delta_y > delta_x {
-				delta_y
-			} else {
-				delta_x
-			};
-			transform.scale = Vec3::new(delta, delta, 1.0);
-			transform.rotation = Quat::from_rotation_z(1.5708);
-		} else {
-			let delta_x = SCREEN_W / window.width();
-			let delta_y = SCREEN_H / window.height();
-			let delta = if delta_y > delta_x {
-				delta_y
-			} else {
-				delta_x
-			};
-			transform.scale = Vec3::new(delta, delta, 1.0);
-			transform.rotation = Quat::from_rotation_z(0.0);
+	let mut highscore = builder()
+		.revertible(true)
+		.revert_to_default_on_deserialization_errors(true)
+		.build()
+		.expect("Err: Could not load high scores");
+	if let Some(legacy) = legacy {
+		if highscore.set(legacy).is_ok() {
+			println!("Migrated old high scores");
 		}
 	}
+	highscore
 }
 
-#[cfg(debug_assertions)]
-pub fn mock_touch(
-	mouse: Res<Input<MouseButton>>,
-	windows: Query<&Window>,
-	mut touch_events: EventWriter<TouchInput>,
-) {
-	let window = windows.single();
-	let touch_phase = if mouse.just_pressed(MouseButton::Left) {
-		Some(TouchPhase::Started)
-	} else if mouse.just_released(MouseButton::Left) {
-		Some(TouchPhase::Ended)
-	} else if mouse.pressed(MouseButton::Left) {
-		Some(TouchPhase::Moved)
-	} else {
-		None
-	};
-	if let (Some(phase), Some(cursor_pos)) = (touch_phase, window.cursor_position()) {
-		touch_events.send(TouchInput {
-			phase: phase,
-			position: cursor_pos,
-			force: None,
-			id: 0,
-		})
-	}
+fn has_flag(name: &str) -> bool {
+	std::env::args().any(|arg| arg == name)
 }
 
-#[cfg(not(debug_assertions))]
-pub fn mock_touch() {}
\ No newline at end of file
+fn arg_value(name: &str) -> Option<String> {
+	let mut args = std::env::args();
+	while let Some(arg) = args.next() {
+		if arg == name {
+			return args.next();
+		}
+	}
+	None
+}
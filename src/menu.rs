@@ -1,25 +1,122 @@
-use bevy::{prelude::*, input::touch::TouchPhase};
+use bevy::{prelude::*, input::touch::TouchPhase, sprite::MaterialMesh2dBundle};
 use bevy_persistent::Persistent;
 
-use crate::util::{AppState, Score, HighScore, Fruit, TEXT_COLOR};
+use crate::camera::touch_to_world;
+use crate::util::{
+  AppState,
+  PauseState,
+  Score,
+  HighScore,
+  TimeAttackHighScore,
+  DailyHighScore,
+  DailyRun,
+  DailyProgress,
+  Leaderboard,
+  GameMode,
+  NewHighScore,
+  LifetimeStats,
+  GameOverReason,
+  Fruit,
+  MainCamera,
+  SCREEN_W,
+  SCREEN_H,
+  TEXT_COLOR,
+  MAX_H_COLOR,
+  MAX_NAME_LEN,
+  FruitTable,
+  format_duration,
+  today,
+};
+use crate::save::{SavedGame, ResumeGame};
+use crate::game::Chain;
 
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
   fn build(&self, app: &mut App) {
-    app.add_systems(OnEnter(AppState::Menu), setup_menu.after(in_state(AppState::Menu)))
+    app.init_resource::<ConfirmQuit>()
+      .add_systems(OnEnter(AppState::Menu), setup_menu
+        .after(in_state(AppState::Menu))
+        .run_if(not(resource_exists::<RestartGame>())))
       .add_systems(Update, on_loop.run_if(in_state(AppState::Menu)))
       .add_systems(OnExit(AppState::Menu), cleanup)
-      .add_systems(OnEnter(AppState::GameOver), setup_game_over)
-      .add_systems(Update, on_loop.run_if(in_state(AppState::GameOver)))
-      .add_systems(OnExit(AppState::GameOver), cleanup);
+      // puzzle levels have their own result screen
+      .add_systems(OnEnter(AppState::GameOver), setup_game_over
+        .run_if(not(resource_equals(GameMode::Puzzle))))
+      .add_systems(Update, (
+          on_loop
+            .run_if(not(resource_exists::<NewHighScore>()))
+            .run_if(not(resource_equals(GameMode::Puzzle))),
+          enter_name::<HighScore>
+            .run_if(resource_exists::<NewHighScore>())
+            .run_if(resource_equals(GameMode::Classic)),
+          enter_name::<TimeAttackHighScore>
+            .run_if(resource_exists::<NewHighScore>())
+            .run_if(resource_equals(GameMode::TimeAttack)),
+          enter_name::<DailyHighScore>
+            .run_if(resource_exists::<NewHighScore>())
+            .run_if(resource_equals(GameMode::Daily)),
+          highlight_offender,
+        ).run_if(in_state(AppState::GameOver)))
+      .add_systems(OnExit(AppState::GameOver), cleanup)
+      .add_systems(OnEnter(AppState::Stats), setup_stats)
+      .add_systems(Update, stats_loop.run_if(in_state(AppState::Stats)))
+      .add_systems(OnExit(AppState::Stats), cleanup)
+      .add_systems(OnEnter(PauseState::Paused), setup_pause)
+      .add_systems(Update, pause_loop.run_if(in_state(PauseState::Paused)))
+      .add_systems(OnExit(PauseState::Paused), cleanup_pause);
   }
 }
 
 #[derive(Component)]
 pub struct MenuItem;
 
-fn setup_menu(mut commands: Commands) {
+// tapping this opens the settings instead of starting a game
+#[derive(Component)]
+struct MenuSettings;
+
+// tapping this opens the statistics
+#[derive(Component)]
+struct MenuStats;
+
+// tapping this switches to the next game mode
+#[derive(Component)]
+struct MenuMode;
+
+// name prompt for a new high score on the game over screen
+#[derive(Component)]
+struct NameEntry;
+
+// everything drawn by the pause menu
+#[derive(Component)]
+struct PauseItem;
+
+// pause menu entries, swapped out while asking to confirm quitting
+#[derive(Component)]
+struct PauseOption;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum PauseAction {
+  Resume,
+  Restart,
+  Quit,
+  ConfirmQuit,
+  CancelQuit,
+}
+
+#[derive(Resource, Default)]
+struct ConfirmQuit(bool);
+
+// passes through the menu straight into a new game
+#[derive(Resource)]
+struct RestartGame;
+
+fn setup_menu(
+  mut commands: Commands,
+  saved: Option<Res<Persistent<SavedGame>>>,
+  mode: Res<GameMode>,
+  progress: Option<Res<Persistent<DailyProgress>>>,
+) {
 
   // title
   commands.spawn((
@@ -68,13 +165,143 @@ fn setup_menu(mut commands: Commands) {
       ..default()
     });
   });
+
+  // mode selector
+  commands.spawn((
+    MenuItem,
+    MenuMode,
+    Text2dBundle {
+      text: Text::from_sections([
+        TextSection::new(
+          mode_label(*mode),
+          TextStyle {
+            font_size: 30.0,
+            color: MAX_H_COLOR,
+            ..default()
+          },
+        ),
+        TextSection::new(
+          mode_hint(*mode, progress.as_deref().map(|p| p.get())),
+          TextStyle {
+            font_size: 20.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+      ]).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(Vec3::new(0.0, 50.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // settings text
+  commands.spawn((
+    MenuItem,
+    MenuSettings,
+    Text2dBundle {
+      text: Text::from_section(
+        "press S for settings",
+        TextStyle {
+          font_size: 22.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, -250.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // statistics text
+  commands.spawn((
+    MenuItem,
+    MenuStats,
+    Text2dBundle {
+      text: Text::from_section(
+        "press T for statistics",
+        TextStyle {
+          font_size: 22.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, -280.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // continue text
+  if saved.is_some_and(|s| s.can_continue()) {
+    commands.spawn((
+      MenuItem,
+      Text2dBundle {
+        text: Text::from_section(
+          "or press C to continue your last game",
+          TextStyle {
+            font_size: 25.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+        transform: Transform::from_translation(Vec3::new(0.0, -30.0, 10.0)),
+        ..default()
+      },
+    ));
+  }
 }
 
 fn setup_game_over(
   mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
   score: Res<Score>,
-  highscore: Res<Persistent<HighScore>>,
+  mode: Res<GameMode>,
+  classic: Option<Res<Persistent<HighScore>>>,
+  time_attack: Option<Res<Persistent<TimeAttackHighScore>>>,
+  daily: Option<Res<Persistent<DailyHighScore>>>,
+  new_high_score: Option<Res<NewHighScore>>,
+  chain: Res<Chain>,
+  reason: Option<Res<GameOverReason>>,
 ) {
+  // the table this game went into
+  let highscore = match *mode {
+    GameMode::Classic => classic.as_ref().map(|h| h.get()),
+    GameMode::TimeAttack => time_attack.as_ref().map(|h| h.table()),
+    GameMode::Daily => daily.as_ref().map(|h| h.table()),
+    GameMode::Zen | GameMode::Puzzle | GameMode::Versus => None,
+  };
+
+  // dim the frozen board
+  commands.spawn((
+    MenuItem,
+    MaterialMesh2dBundle {
+      mesh: meshes.add(shape::Quad::new(Vec2::new(SCREEN_W * 2.0, SCREEN_H * 2.0)).into()).into(),
+      material: materials.add(ColorMaterial::from(Color::rgba(0.0, 0.0, 0.0, 0.4))),
+      transform: Transform::from_translation(Vec3::new(0.0, 0.0, 8.0)),
+      ..default()
+    },
+  ));
+
+  // reason
+  if let Some(reason) = reason {
+    let mut text = reason.to_string();
+    text[..1].make_ascii_uppercase();
+    commands.spawn((
+      MenuItem,
+      Text2dBundle {
+        text: Text::from_section(
+          text,
+          TextStyle {
+            font_size: 30.0,
+            color: TEXT_COLOR,
+            ..default()
+          }
+        ),
+        transform: Transform::from_translation(Vec3::new(0.0, 80.0, 10.0)),
+        ..default()
+      },
+    ));
+  }
 
   // game over text
   commands.spawn((
@@ -115,7 +342,7 @@ fn setup_game_over(
     MenuItem,
     Text2dBundle {
       text: Text::from_section(
-        "High score: ".to_owned() + &highscore.0[0].to_string(),
+        "High score: ".to_owned() + &highscore.map(|h| h.best()).unwrap_or(score.1).to_string(),
         TextStyle {
           font_size: 30.0, 
         color: TEXT_COLOR,
@@ -126,27 +353,308 @@ fn setup_game_over(
       ..default()
     }
   ));
+
+  // longest chain
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_section(
+        "Longest chain: ".to_owned() + &chain.longest.to_string(),
+        TextStyle {
+          font_size: 30.0,
+          color: TEXT_COLOR,
+          ..default()
+        }
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, -60.0, 10.0)),
+      ..default()
+    }
+  ));
+
+  // name prompt, starts out with the last name used
+  let Some(rank) = new_high_score.map(|r| r.0) else {
+    return;
+  };
+  let name = highscore.and_then(|h| h.entries.get(rank).map(|e| e.name.clone())).unwrap_or_default();
+  commands.spawn((
+    MenuItem,
+    NameEntry,
+    Text2dBundle {
+      text: Text::from_sections([
+        TextSection::new(
+          format!("New high score, #{} on the board!\nName: ", rank + 1),
+          TextStyle {
+            font_size: 30.0,
+            color: TEXT_COLOR,
+            ..default()
+          }
+        ),
+        TextSection::new(
+          name + "_",
+          TextStyle {
+            font_size: 30.0,
+            color: MAX_H_COLOR,
+            ..default()
+          }
+        ),
+        TextSection::new(
+          "\ntype a name, then press enter or tap to save",
+          TextStyle {
+            font_size: 20.0,
+            color: TEXT_COLOR,
+            ..default()
+          }
+        ),
+      ]).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(Vec3::new(0.0, -130.0, 10.0)),
+      ..default()
+    }
+  ));
+}
+
+// edits the new entry's name in place, enter or a tap saves it
+fn enter_name<T: Leaderboard>(
+  mut commands: Commands,
+  keys: Res<Input<KeyCode>>,
+  mut characters: EventReader<ReceivedCharacter>,
+  mut touch_events: EventReader<TouchInput>,
+  new_high_score: Res<NewHighScore>,
+  leaderboard: Option<ResMut<Persistent<T>>>,
+  mut name_text: Query<&mut Text, With<NameEntry>>,
+) {
+  let rank = new_high_score.0;
+  let Some(mut leaderboard) = leaderboard.filter(|h| rank < h.table().entries.len()) else {
+    commands.remove_resource::<NewHighScore>();
+    return;
+  };
+  let highscore = leaderboard.table_mut();
+
+  let mut name = highscore.entries[rank].name.clone();
+  for event in characters.iter() {
+    let c = event.char;
+    if (c.is_alphanumeric() || c == ' ' || c == '-' || c == '_') && name.chars().count() < MAX_NAME_LEN {
+      name.push(c);
+    }
+  }
+  if keys.just_pressed(KeyCode::Back) {
+    name.pop();
+  }
+  // only touch the resource on edits, the high score panel redraws on change
+  if name != highscore.entries[rank].name {
+    highscore.entries[rank].name = name.clone();
+    if let Ok(mut text) = name_text.get_single_mut() {
+      text.sections[1].value = name.clone() + "_";
+    }
+  }
+
+  let tapped = touch_events.iter().any(|t| t.phase == TouchPhase::Ended);
+  if keys.just_pressed(KeyCode::Return) || tapped {
+    let name = name.trim().to_string();
+    highscore.entries[rank].name = name.clone();
+    highscore.last_name = name.clone();
+    leaderboard.persist().ok();
+    commands.remove_resource::<NewHighScore>();
+    if let Ok(mut text) = name_text.get_single_mut() {
+      text.sections[0].value = "Saved as ".to_string();
+      text.sections[1].value = if name.is_empty() { "???".to_string() } else { name };
+      text.sections[2].value = "\npress enter to try again".to_string();
+    }
+  }
 }
 
 fn on_loop(
+  mut commands: Commands,
+  state: Res<State<AppState>>,
   mut next_state: ResMut<NextState<AppState>>,
   keys: Res<Input<KeyCode>>,
   mut touch_events: EventReader<TouchInput>,
+  saved: Option<Res<Persistent<SavedGame>>>,
+  restart: Option<Res<RestartGame>>,
+  mut mode: ResMut<GameMode>,
+  mut daily: ResMut<DailyRun>,
+  progress: Option<Res<Persistent<DailyProgress>>>,
+  camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  settings_text: Query<&Transform, With<MenuSettings>>,
+  stats_text: Query<&Transform, With<MenuStats>>,
+  mut mode_text: Query<(&Transform, &mut Text), With<MenuMode>>,
 ) {
-  
+  if restart.is_some() {
+    commands.remove_resource::<RestartGame>();
+    next_state.set(AppState::InGame);
+    return;
+  }
+
+  // puzzles start from the level select, versus has its own screen
+  let start = match *mode {
+    GameMode::Puzzle if *state.get() == AppState::Menu => AppState::LevelSelect,
+    GameMode::Versus if *state.get() == AppState::Menu => AppState::Versus,
+    _ => AppState::InGame,
+  };
+
   if keys.just_pressed(KeyCode::Return) {
-    next_state.set(AppState::InGame)
+    next_state.set(start.clone())
+  }
+
+  // continue saved game, in the mode it was started in
+  if keys.just_pressed(KeyCode::C) && *state.get() == AppState::Menu {
+    if let Some(saved) = saved.filter(|s| s.can_continue()) {
+      *mode = saved.mode;
+      *daily = saved.daily;
+      commands.insert_resource(ResumeGame);
+      next_state.set(AppState::InGame)
+    }
+  }
+
+  // switch mode
+  let mut step = 0;
+  if keys.just_pressed(KeyCode::Left) {
+    step -= 1;
+  }
+  if keys.just_pressed(KeyCode::Right) {
+    step += 1;
+  }
+
+  // open settings
+  if keys.just_pressed(KeyCode::S) && *state.get() == AppState::Menu {
+    next_state.set(AppState::Settings)
+  }
+
+  // open statistics
+  if keys.just_pressed(KeyCode::T) && *state.get() == AppState::Menu {
+    next_state.set(AppState::Stats)
   }
 
   // handle touch
   for touch in touch_events.iter() {
     if touch.phase == TouchPhase::Ended {
-      next_state.set(AppState::InGame)
+      let pos = touch_to_world(&camera, touch.position);
+      let on_text = |t: &Transform| pos.is_some_and(|pos| {
+        (pos.y - t.translation.y).abs() < 15.0 && pos.x.abs() < 150.0
+      });
+      if settings_text.iter().any(on_text) {
+        next_state.set(AppState::Settings)
+      } else if stats_text.iter().any(on_text) {
+        next_state.set(AppState::Stats)
+      } else if mode_text.iter().any(|(t, _)| on_text(t)) {
+        step += 1;
+      } else {
+        next_state.set(start.clone())
+      }
     }
   }
+
+  if step != 0 && *state.get() == AppState::Menu {
+    *mode = mode.cycle(step);
+    for (_, mut text) in mode_text.iter_mut() {
+      text.sections[0].value = mode_label(*mode);
+      text.sections[1].value = mode_hint(*mode, progress.as_deref().map(|p| p.get()));
+    }
+  }
+}
+
+fn mode_label(mode: GameMode) -> String {
+  format!("< {} >", mode.name())
+}
+
+// the daily challenge shows the streak and whether today's attempt is used up
+fn mode_hint(mode: GameMode, progress: Option<&DailyProgress>) -> String {
+  match (mode, progress) {
+    (GameMode::Daily, Some(progress)) => {
+      let day = today();
+      let practice = if progress.played(day) { ", practice only today" } else { "" };
+      format!("\n{} day streak{}", progress.current_streak(day), practice)
+    },
+    _ => "\nleft/right to change mode".to_string(),
+  }
+}
+
+fn setup_stats(
+  mut commands: Commands,
+  lifetime: Option<Res<Persistent<LifetimeStats>>>,
+  table: Res<FruitTable>,
+) {
+  let lifetime = lifetime.map(|l| l.get().clone()).unwrap_or_default();
+
+  // title
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_section(
+        "Statistics",
+        TextStyle {
+          font_size: 60.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 220.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // totals
+  let merges = lifetime.merges.iter().enumerate()
+    .filter(|(_, &count)| count > 0)
+    .map(|(tier, count)| format!("#{}: {}", tier, count))
+    .collect::<Vec<_>>();
+  let game_overs = lifetime.game_overs.iter()
+    .map(|(kind, count)| format!("{}: {}", kind, count))
+    .collect::<Vec<_>>();
+  let lines = [
+    format!("Games played: {}", lifetime.games_played),
+    format!("Total playtime: {}", format_playtime(lifetime.playtime)),
+    format!("Average score: {:.0}", lifetime.average_score()),
+    format!("Total drops: {}", lifetime.total_drops),
+    format!("#{} fruits created: {}", table.top_id(), lifetime.top_fruits),
+    format!("Merges by tier:\n{}", if merges.is_empty() { "-".to_string() } else { merges.join("  ") }),
+    format!("Game overs:\n{}", if game_overs.is_empty() { "-".to_string() } else { game_overs.join("  ") }),
+  ];
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_section(
+        lines.join("\n"),
+        TextStyle {
+          font_size: 26.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(Vec3::new(0.0, 10.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // help text
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_section(
+        "Esc or tap to go back",
+        TextStyle {
+          font_size: 20.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, -250.0, 10.0)),
+      ..default()
+    },
+  ));
+}
+
+fn stats_loop(
+  keys: Res<Input<KeyCode>>,
+  mut touch_events: EventReader<TouchInput>,
+  mut next_state: ResMut<NextState<AppState>>,
+) {
+  let tapped = touch_events.iter().any(|t| t.phase == TouchPhase::Ended);
+  if tapped || keys.any_just_pressed([KeyCode::Escape, KeyCode::Return, KeyCode::T]) {
+    next_state.set(AppState::Menu);
+  }
 }
 
-fn cleanup(
+pub(crate) fn cleanup(
   mut commands: Commands, 
   menu_items: Query<Entity, With<MenuItem>>,
   fruits: Query<Entity, With<Fruit>>,
@@ -157,4 +665,179 @@ fn cleanup(
   for fruit in fruits.iter() {
     commands.entity(fruit).despawn_recursive();
   }
-}
\ No newline at end of file
+}
+
+// ring around the fruit that ended the game, drawn over the dimmed board
+fn highlight_offender(
+  mut gizmos: Gizmos,
+  reason: Option<Res<GameOverReason>>,
+  fruits: Query<(&Transform, &Fruit)>,
+) {
+  let Some(offender) = reason.and_then(|r| r.fruit()) else {
+    return;
+  };
+  // the fruit may be gone already, e.g. merged on the last tick
+  let (position, radius) = match fruits.get(offender.entity) {
+    Ok((transform, fruit)) => (transform.translation.truncate(), fruit.size / 2.0),
+    Err(_) => (offender.position, 20.0),
+  };
+  gizmos.circle_2d(position, radius + 6.0, MAX_H_COLOR);
+  gizmos.circle_2d(position, radius + 8.0, MAX_H_COLOR);
+}
+
+fn setup_pause(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  mut confirm: ResMut<ConfirmQuit>,
+) {
+  confirm.0 = false;
+
+  // dim the frozen board
+  commands.spawn((
+    PauseItem,
+    MaterialMesh2dBundle {
+      mesh: meshes.add(shape::Quad::new(Vec2::new(SCREEN_W * 2.0, SCREEN_H * 2.0)).into()).into(),
+      material: materials.add(ColorMaterial::from(Color::rgba(0.0, 0.0, 0.0, 0.6))),
+      transform: Transform::from_translation(Vec3::new(0.0, 0.0, 20.0)),
+      ..default()
+    },
+  ));
+  spawn_pause_options(&mut commands, false);
+}
+
+fn pause_loop(
+  mut commands: Commands,
+  keys: Res<Input<KeyCode>>,
+  mut touch_events: EventReader<TouchInput>,
+  camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  options: Query<(Entity, Option<&PauseAction>, &Transform), With<PauseOption>>,
+  mut confirm: ResMut<ConfirmQuit>,
+  mut next_state: ResMut<NextState<AppState>>,
+  mut next_pause: ResMut<NextState<PauseState>>,
+) {
+  let mut action = None;
+  if confirm.0 {
+    if keys.just_pressed(KeyCode::Y) || keys.just_pressed(KeyCode::Return) {
+      action = Some(PauseAction::ConfirmQuit);
+    } else if keys.just_pressed(KeyCode::N) || keys.just_pressed(KeyCode::Escape) {
+      action = Some(PauseAction::CancelQuit);
+    }
+  } else if keys.just_pressed(KeyCode::Escape) || keys.just_pressed(KeyCode::P) || keys.just_pressed(KeyCode::Return) {
+    action = Some(PauseAction::Resume);
+  } else if keys.just_pressed(KeyCode::R) {
+    action = Some(PauseAction::Restart);
+  } else if keys.just_pressed(KeyCode::Q) {
+    action = Some(PauseAction::Quit);
+  }
+
+  // tap an option
+  for touch in touch_events.iter() {
+    if touch.phase != TouchPhase::Ended {
+      continue;
+    }
+    let Some(pos) = touch_to_world(&camera, touch.position) else {
+      continue;
+    };
+    for (_, option, transform) in options.iter() {
+      if let Some(option) = option {
+        if (pos.y - transform.translation.y).abs() < 20.0 && pos.x.abs() < 200.0 {
+          action = Some(*option);
+        }
+      }
+    }
+  }
+
+  match action {
+    Some(PauseAction::Resume) => next_pause.set(PauseState::Running),
+    Some(PauseAction::Restart) => {
+      commands.insert_resource(GameOverReason::Quit);
+      commands.insert_resource(RestartGame);
+      next_state.set(AppState::Menu);
+      next_pause.set(PauseState::Running);
+    },
+    Some(PauseAction::ConfirmQuit) => {
+      commands.insert_resource(GameOverReason::Quit);
+      next_state.set(AppState::Menu);
+      next_pause.set(PauseState::Running);
+    },
+    Some(PauseAction::Quit) | Some(PauseAction::CancelQuit) => {
+      confirm.0 = action == Some(PauseAction::Quit);
+      for (e, _, _) in options.iter() {
+        commands.entity(e).despawn_recursive();
+      }
+      spawn_pause_options(&mut commands, confirm.0);
+    },
+    None => {},
+  }
+}
+
+fn cleanup_pause(
+  mut commands: Commands,
+  pause_items: Query<Entity, With<PauseItem>>,
+) {
+  for e in pause_items.iter() {
+    commands.entity(e).despawn_recursive();
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+// 3h 25m, or m:ss under an hour
+fn format_playtime(seconds: f64) -> String {
+  if seconds < 3600.0 {
+    return format_duration(seconds as f32);
+  }
+  let minutes = (seconds / 60.0) as u64;
+  format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+fn spawn_pause_options(commands: &mut Commands, confirm_quit: bool) {
+  let (title, options) = if confirm_quit {
+    ("Quit to menu? This run will be lost.", vec![
+      ("Yes (Y)", PauseAction::ConfirmQuit),
+      ("No (N)", PauseAction::CancelQuit),
+    ])
+  } else {
+    ("Paused", vec![
+      ("Resume (Esc)", PauseAction::Resume),
+      ("Restart (R)", PauseAction::Restart),
+      ("Quit to Menu (Q)", PauseAction::Quit),
+    ])
+  };
+
+  commands.spawn((
+    PauseItem,
+    PauseOption,
+    Text2dBundle {
+      text: Text::from_section(
+        title,
+        TextStyle {
+          font_size: 50.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 100.0, 21.0)),
+      ..default()
+    },
+  ));
+  for (i, (label, action)) in options.into_iter().enumerate() {
+    commands.spawn((
+      PauseItem,
+      PauseOption,
+      action,
+      Text2dBundle {
+        text: Text::from_section(
+          label,
+          TextStyle {
+            font_size: 30.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+        transform: Transform::from_translation(Vec3::new(0.0, 20.0 - 50.0 * i as f32, 21.0)),
+        ..default()
+      },
+    ));
+  }
+}
@@ -0,0 +1,545 @@
+use std::{collections::BTreeSet, path::Path};
+use bevy::{prelude::*, input::touch::TouchPhase, sprite::MaterialMesh2dBundle};
+use bevy_rapier2d::prelude::*;
+use bevy_persistent::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::camera::touch_to_world;
+use crate::util::{
+  AppState,
+  PauseState,
+  GameMode,
+  GameOverReason,
+  Fruit,
+  FruitTable,
+  MainCamera,
+  MIN_SPEED,
+  CLOCK_POS,
+  SCREEN_W,
+  SCREEN_H,
+  TEXT_COLOR,
+  MAX_H_COLOR,
+};
+use crate::game::{
+  GameSet,
+  Positions,
+  ActiveFruit,
+  FruitQueue,
+  HoldSlot,
+  GameStats,
+  FruitMerged,
+  UIComponent,
+  spawn_collider_fruit,
+};
+use crate::menu::{MenuItem, cleanup};
+
+// ticks the board has to stay still before running out of drops fails the level
+const SETTLE_TICKS: u32 = 30;
+
+// the levels in assets/levels, for builds that can't read them from disk
+const BUILTIN_LEVELS: [(&str, &str); 3] = [
+  ("01_first_merge", include_str!("../assets/levels/01_first_merge.ron")),
+  ("02_spring_cleaning", include_str!("../assets/levels/02_spring_cleaning.ron")),
+  ("03_eight_in_six", include_str!("../assets/levels/03_eight_in_six.ron")),
+];
+
+pub struct PuzzlePlugin;
+
+impl Plugin for PuzzlePlugin {
+  fn build(&self, app: &mut App) {
+    app.insert_resource(Levels::load())
+      .init_resource::<CurrentLevel>()
+      .init_resource::<LevelState>()
+      .add_systems(OnEnter(AppState::LevelSelect), setup_level_select)
+      .add_systems(Update, level_select_loop.run_if(in_state(AppState::LevelSelect)))
+      .add_systems(OnExit(AppState::LevelSelect), cleanup)
+      .add_systems(OnEnter(AppState::InGame), setup_level
+        .after(GameSet::Reset)
+        .run_if(resource_equals(GameMode::Puzzle)))
+      // after the step, so the settle count sees the same velocities every tick
+      .add_systems(FixedUpdate, check_goal
+        .after(PhysicsSet::Writeback)
+        .run_if(in_state(PauseState::Running))
+        .run_if(in_state(AppState::InGame))
+        .run_if(resource_equals(GameMode::Puzzle)))
+      .add_systems(Update, update_level_label
+        .run_if(in_state(AppState::InGame))
+        .run_if(resource_equals(GameMode::Puzzle)))
+      .add_systems(OnEnter(AppState::GameOver), setup_result.run_if(resource_equals(GameMode::Puzzle)))
+      .add_systems(Update, result_loop
+        .run_if(in_state(AppState::GameOver))
+        .run_if(resource_equals(GameMode::Puzzle)));
+  }
+}
+
+// -- RESOURCES --
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Goal {
+  // merge up to a fruit of this tier within so many drops
+  Create { tier: i32, drops: u32 },
+  // no fruit of this tier left in the cup
+  Clear { tier: i32 },
+}
+
+impl std::fmt::Display for Goal {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Goal::Create { tier, drops } => write!(f, "make a #{} within {} drops", tier, drops),
+      Goal::Clear { tier } => write!(f, "clear every #{}", tier),
+    }
+  }
+}
+
+// a fruit in the cup when the level starts, x from the middle and y from the floor
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct LevelFruit {
+  pub id: i32,
+  pub x: f32,
+  pub y: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Level {
+  // file name without .ron, completion is saved under it
+  #[serde(skip)]
+  pub id: String,
+  pub name: String,
+  pub fruits: Vec<LevelFruit>,
+  pub drops: Vec<i32>,
+  pub goal: Goal,
+}
+
+impl Level {
+  pub fn from_ron(id: &str, ron_str: &str) -> Result<Self, String> {
+    let mut level: Level = ron::from_str(ron_str).map_err(|e| e.to_string())?;
+    if level.drops.is_empty() {
+      return Err("no drops".to_string());
+    }
+    level.id = id.to_string();
+    Ok(level)
+  }
+
+  // drops the player gets, the goal can allow fewer than the sequence holds
+  pub fn drop_limit(&self) -> u32 {
+    match self.goal {
+      Goal::Create { drops, .. } => drops.min(self.drops.len() as u32),
+      Goal::Clear { .. } => self.drops.len() as u32,
+    }
+  }
+}
+
+// every level in file name order
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Levels(pub Vec<Level>);
+
+impl Levels {
+  pub fn load() -> Self {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      let dir = bevy::asset::FileAssetIo::get_base_path().join("assets/levels");
+      match Levels::from_dir(&dir) {
+        Ok(levels) if !levels.0.is_empty() => return levels,
+        Ok(_) => println!("No levels in {}", dir.display()),
+        Err(e) => println!("Could not load levels {}: {}", dir.display(), e),
+      }
+    }
+    Levels::builtin()
+  }
+
+  pub fn from_dir(dir: &Path) -> Result<Self, String> {
+    let mut paths = std::fs::read_dir(dir).map_err(|e| e.to_string())?
+      .filter_map(|entry| entry.ok().map(|e| e.path()))
+      .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+      .collect::<Vec<_>>();
+    paths.sort();
+    paths.iter().map(|path| {
+      let id = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+      std::fs::read_to_string(path).map_err(|e| e.to_string())
+        .and_then(|s| Level::from_ron(&id, &s))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+    }).collect::<Result<Vec<_>, _>>().map(Levels)
+  }
+
+  pub fn builtin() -> Self {
+    // compiled in, so a broken one is a bug rather than a missing level
+    Levels(BUILTIN_LEVELS.iter().map(|(id, s)| {
+      Level::from_ron(id, s).unwrap_or_else(|e| panic!("Err: built-in level assets/levels/{}.ron is broken: {}", id, e))
+    }).collect())
+  }
+}
+
+// index into Levels picked on the level select
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct CurrentLevel(pub usize);
+
+// ids of the levels solved at least once
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PuzzleProgress {
+  pub completed: BTreeSet<String>,
+}
+
+// how the level being played is going
+#[derive(Resource, Default)]
+struct LevelState {
+  created: i32,
+  settled_ticks: u32,
+}
+
+// -- COMPONENTS --
+#[derive(Component)]
+struct LevelLabel;
+
+// a level on the level select
+#[derive(Component)]
+struct LevelItem(usize);
+
+// -- SYSTEMS --
+fn setup_level_select(
+  mut commands: Commands,
+  levels: Res<Levels>,
+  current: Res<CurrentLevel>,
+  progress: Option<Res<Persistent<PuzzleProgress>>>,
+) {
+  // title
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_section(
+        "Puzzles",
+        TextStyle {
+          font_size: 60.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 230.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // one entry per level
+  for (i, level) in levels.0.iter().enumerate() {
+    let done = progress.as_ref().is_some_and(|p| p.completed.contains(&level.id));
+    commands.spawn((
+      MenuItem,
+      LevelItem(i),
+      Text2dBundle {
+        text: Text::from_sections([
+          TextSection::new(
+            format!("{}. {}{}", i + 1, level.name, if done { "  (done)" } else { "" }),
+            TextStyle {
+              font_size: 30.0,
+              color: item_color(i == current.0),
+              ..default()
+            },
+          ),
+          TextSection::new(
+            format!("\n{}", level.goal),
+            TextStyle {
+              font_size: 20.0,
+              color: TEXT_COLOR,
+              ..default()
+            },
+          ),
+        ]).with_alignment(TextAlignment::Center),
+        transform: Transform::from_translation(Vec3::new(0.0, 150.0 - i as f32 * 60.0, 10.0)),
+        ..default()
+      },
+    ));
+  }
+
+  // help text
+  let help = if levels.0.is_empty() {
+    "no levels found, Esc to go back"
+  } else {
+    "up/down to pick, enter or tap to play, Esc to go back"
+  };
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_section(
+        help,
+        TextStyle {
+          font_size: 22.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, -270.0, 10.0)),
+      ..default()
+    },
+  ));
+}
+
+fn level_select_loop(
+  keys: Res<Input<KeyCode>>,
+  mut touch_events: EventReader<TouchInput>,
+  mut next_state: ResMut<NextState<AppState>>,
+  levels: Res<Levels>,
+  mut current: ResMut<CurrentLevel>,
+  camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  mut items: Query<(&LevelItem, &Transform, &mut Text)>,
+) {
+  if keys.just_pressed(KeyCode::Escape) {
+    next_state.set(AppState::Menu);
+    return;
+  }
+  if levels.0.is_empty() {
+    return;
+  }
+
+  let last = levels.0.len() - 1;
+  let mut selected = current.0.min(last);
+  if keys.just_pressed(KeyCode::Up) {
+    selected = selected.saturating_sub(1);
+  }
+  if keys.just_pressed(KeyCode::Down) {
+    selected = (selected + 1).min(last);
+  }
+  let mut start = keys.just_pressed(KeyCode::Return);
+
+  // tap a level to play it
+  for touch in touch_events.iter() {
+    if touch.phase != TouchPhase::Ended {
+      continue;
+    }
+    let Some(pos) = touch_to_world(&camera, touch.position) else {
+      continue;
+    };
+    for (item, transform, _) in items.iter() {
+      if (pos.y - transform.translation.y).abs() < 25.0 && pos.x.abs() < 250.0 {
+        selected = item.0;
+        start = true;
+      }
+    }
+  }
+
+  if selected != current.0 {
+    current.0 = selected;
+    for (item, _, mut text) in items.iter_mut() {
+      text.sections[0].style.color = item_color(item.0 == selected);
+    }
+  }
+  if start {
+    next_state.set(AppState::InGame);
+  }
+}
+
+// fills the cup and the drop sequence from the level file
+fn setup_level(
+  mut commands: Commands,
+  positions: Res<Positions>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  table: Res<FruitTable>,
+  levels: Res<Levels>,
+  current: Res<CurrentLevel>,
+  mut queue: ResMut<FruitQueue>,
+  mut level_state: ResMut<LevelState>,
+) {
+  let Some(level) = levels.0.get(current.0) else {
+    return;
+  };
+  for fruit in level.fruits.iter() {
+    // z 3.5 is the one without spin
+    let pos = Vec3::new(fruit.x, positions.cup_base_y + fruit.y, 3.5);
+    spawn_collider_fruit(&mut commands, &mut meshes, &mut materials, &table, fruit.id, pos);
+  }
+  // only the drops the goal allows are handed out, there is nothing to drop past the limit
+  queue.fixed = Some(level.drops.iter().take(level.drop_limit() as usize).copied().collect());
+  *level_state = LevelState::default();
+
+  // name, goal and drops left, filled in by update_level_label
+  commands.spawn((
+    UIComponent,
+    LevelLabel,
+    Text2dBundle {
+      text: Text::from_section(
+        "",
+        TextStyle {
+          font_size: 24.0,
+          color: TEXT_COLOR,
+          ..default()
+        }
+      ).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(CLOCK_POS),
+      ..default()
+    },
+  ));
+}
+
+fn update_level_label(
+  mut label: Query<&mut Text, With<LevelLabel>>,
+  levels: Res<Levels>,
+  current: Res<CurrentLevel>,
+  stats: Res<GameStats>,
+) {
+  let Some(level) = levels.0.get(current.0) else {
+    return;
+  };
+  if let Ok(mut text) = label.get_single_mut() {
+    let left = level.drop_limit().saturating_sub(stats.drops);
+    text.sections[0].value = format!("{}\n{}\n{} drops left", level.name, level.goal, left);
+  }
+}
+
+// ends the level once the goal is met, or once the drops are used up and the
+// board has come to rest without it
+fn check_goal(
+  mut commands: Commands,
+  mut next_state: ResMut<NextState<AppState>>,
+  levels: Res<Levels>,
+  current: Res<CurrentLevel>,
+  mut level_state: ResMut<LevelState>,
+  mut merged: EventReader<FruitMerged>,
+  fruits: Query<(&Fruit, &Velocity)>,
+  active_fruit: Query<(), With<ActiveFruit>>,
+  queue: Res<FruitQueue>,
+  hold: Res<HoldSlot>,
+  stats: Res<GameStats>,
+) {
+  for merge in merged.iter() {
+    level_state.created = level_state.created.max(merge.output_id);
+  }
+  // end_game got there first
+  if next_state.0.is_some() {
+    return;
+  }
+  let Some(level) = levels.0.get(current.0) else {
+    return;
+  };
+
+  if fruits.iter().all(|(_, v)| v.linvel.length() < MIN_SPEED) {
+    level_state.settled_ticks += 1;
+  } else {
+    level_state.settled_ticks = 0;
+  }
+
+  let complete = match level.goal {
+    Goal::Create { tier, .. } => level_state.created >= tier,
+    Goal::Clear { tier } => fruits.iter().all(|(fruit, _)| fruit.id != tier),
+  };
+  // a held fruit can still be dropped
+  let out_of_drops = stats.drops >= level.drop_limit()
+    || (queue.is_exhausted() && active_fruit.is_empty() && hold.fruit.is_none());
+  let reason = if complete {
+    GameOverReason::LevelComplete
+  } else if out_of_drops && level_state.settled_ticks >= SETTLE_TICKS {
+    GameOverReason::OutOfDrops
+  } else {
+    return;
+  };
+  commands.insert_resource(reason);
+  next_state.set(AppState::GameOver);
+}
+
+// success or failure screen, a solved level is saved here
+fn setup_result(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  levels: Res<Levels>,
+  current: Res<CurrentLevel>,
+  reason: Option<Res<GameOverReason>>,
+  progress: Option<ResMut<Persistent<PuzzleProgress>>>,
+) {
+  let complete = reason.as_deref() == Some(&GameOverReason::LevelComplete);
+  if complete {
+    if let (Some(mut progress), Some(level)) = (progress, levels.0.get(current.0)) {
+      if progress.completed.insert(level.id.clone()) {
+        progress.persist().ok();
+      }
+    }
+  }
+
+  // dim the frozen board
+  commands.spawn((
+    MenuItem,
+    MaterialMesh2dBundle {
+      mesh: meshes.add(shape::Quad::new(Vec2::new(SCREEN_W * 2.0, SCREEN_H * 2.0)).into()).into(),
+      material: materials.add(ColorMaterial::from(Color::rgba(0.0, 0.0, 0.0, 0.4))),
+      transform: Transform::from_translation(Vec3::new(0.0, 0.0, 8.0)),
+      ..default()
+    },
+  ));
+
+  let title = if complete { "Level complete!" } else { "Level failed" };
+  let detail = match reason.as_deref() {
+    Some(reason) if !complete => {
+      let mut text = reason.to_string();
+      text[..1].make_ascii_uppercase();
+      text
+    },
+    _ => String::new(),
+  };
+  let has_next = current.0 + 1 < levels.0.len();
+  let help = match (complete, has_next) {
+    (true, true) => "enter or tap for the next level, Esc for the level select",
+    (true, false) => "that was the last one! enter to play it again, Esc for the level select",
+    (false, _) => "enter or tap to try again, Esc for the level select",
+  };
+  commands.spawn((
+    MenuItem,
+    Text2dBundle {
+      text: Text::from_sections([
+        TextSection::new(
+          format!("{}\n", title),
+          TextStyle {
+            font_size: 50.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+        TextSection::new(
+          format!("{}\n", detail),
+          TextStyle {
+            font_size: 26.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+        TextSection::new(
+          help,
+          TextStyle {
+            font_size: 22.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+      ]).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(Vec3::new(0.0, 40.0, 10.0)),
+      ..default()
+    },
+  ));
+}
+
+fn result_loop(
+  keys: Res<Input<KeyCode>>,
+  mut touch_events: EventReader<TouchInput>,
+  mut next_state: ResMut<NextState<AppState>>,
+  levels: Res<Levels>,
+  mut current: ResMut<CurrentLevel>,
+  reason: Option<Res<GameOverReason>>,
+) {
+  if keys.just_pressed(KeyCode::Escape) {
+    next_state.set(AppState::LevelSelect);
+    return;
+  }
+  let tapped = touch_events.iter().any(|t| t.phase == TouchPhase::Ended);
+  if keys.just_pressed(KeyCode::Return) || tapped {
+    let complete = reason.as_deref() == Some(&GameOverReason::LevelComplete);
+    if complete && current.0 + 1 < levels.0.len() {
+      current.0 += 1;
+    }
+    next_state.set(AppState::InGame);
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+fn item_color(selected: bool) -> Color {
+  if selected {
+    MAX_H_COLOR
+  } else {
+    TEXT_COLOR
+  }
+}
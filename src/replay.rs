@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use bevy_persistent::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::util::{AppState, GameRng, GameMode};
+use crate::settings::{Settings, MoveSpeedOverride};
+use crate::game::{GameSet, Controls};
+use crate::save::ResumeGame;
+use crate::puzzle::{Levels, CurrentLevel};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<ReplayMode>()
+      .init_resource::<PlaybackCursor>()
+      .init_resource::<Recording>()
+      // before SavePlugin takes ResumeGame away
+      .add_systems(OnEnter(AppState::InGame), (
+          start_replay.before(GameSet::Reset),
+          use_replay_rules
+            .before(GameSet::Reset)
+            .run_if(resource_equals(ReplayMode::Playback)),
+        ))
+      // record after anything that overrides Controls
+      .add_systems(FixedUpdate, record_controls
+        .in_set(GameSet::Logic)
+        .run_if(resource_equals(ReplayMode::Record))
+        .run_if(is_recording)
+        .run_if(in_state(AppState::InGame)))
+      .add_systems(FixedUpdate, playback_controls
+        .in_set(GameSet::Control)
+        .run_if(resource_equals(ReplayMode::Playback))
+        .run_if(in_state(AppState::InGame)))
+      .add_systems(OnExit(AppState::InGame), save_replay
+        .run_if(resource_equals(ReplayMode::Record))
+        .run_if(is_recording));
+  }
+}
+
+// -- RESOURCES --
+// controls for a single fixed tick
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct ControlFrame {
+  pub move_dir: f32,
+  pub drop: bool,
+  pub hold: bool,
+  pub end_game: bool,
+}
+
+#[derive(Resource, Serialize, Deserialize, Debug)]
+pub struct Replay {
+  // a daily game's fruits come from its seed like any other
+  pub seed: u64,
+  // rules the game was played under, playback switches to them
+  pub mode: GameMode,
+  // id of the puzzle level played
+  pub level: Option<String>,
+  // move_dir is scaled by this, playback has to use the same speed
+  pub move_speed: f32,
+  pub frames: Vec<ControlFrame>,
+}
+
+impl Default for Replay {
+  fn default() -> Self {
+    Replay { seed: 0, mode: GameMode::Classic, level: None, move_speed: Settings::default().move_speed, frames: Vec::new() }
+  }
+}
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ReplayMode {
+  #[default]
+  Record,
+  Playback,
+}
+
+#[derive(Resource, Default)]
+struct PlaybackCursor(usize);
+
+// a continued game starts from a board the replay can't store, those aren't
+// recorded and the last replay is kept
+#[derive(Resource, Default)]
+struct Recording(bool);
+
+// -- SYSTEMS --
+fn start_replay(
+  mut commands: Commands,
+  mode: Res<ReplayMode>,
+  resume: Option<Res<ResumeGame>>,
+  mut replay: ResMut<Persistent<Replay>>,
+  mut cursor: ResMut<PlaybackCursor>,
+  mut recording: ResMut<Recording>,
+) {
+  cursor.0 = 0;
+  recording.0 = *mode == ReplayMode::Record && resume.is_none();
+  if recording.0 {
+    replay.frames.clear();
+  }
+  if *mode == ReplayMode::Playback {
+    commands.insert_resource(MoveSpeedOverride(replay.move_speed));
+  }
+}
+
+// plays back under the rules the game was recorded with, whatever the menu picked
+pub(crate) fn use_replay_rules(
+  replay: Res<Persistent<Replay>>,
+  mut mode: ResMut<GameMode>,
+  levels: Option<Res<Levels>>,
+  current: Option<ResMut<CurrentLevel>>,
+) {
+  *mode = replay.mode;
+  if let (Some(id), Some(levels), Some(mut current)) = (&replay.level, levels, current) {
+    match levels.0.iter().position(|level| level.id == *id) {
+      Some(i) => current.0 = i,
+      None => println!("Err: replay is of level {}, which isn't installed", id),
+    }
+  }
+}
+
+fn record_controls(
+  mut replay: ResMut<Persistent<Replay>>,
+  controls: Query<&Controls>,
+) {
+  if let Ok(controls) = controls.get_single() {
+    replay.frames.push(ControlFrame {
+      move_dir: controls.move_dir,
+      drop: controls.drop,
+      hold: controls.hold,
+      end_game: controls.end_game,
+    });
+  }
+}
+
+fn playback_controls(
+  replay: Res<Persistent<Replay>>,
+  mut cursor: ResMut<PlaybackCursor>,
+  mut controls: Query<&mut Controls>,
+) {
+  let Ok(mut controls) = controls.get_single_mut() else {
+    return;
+  };
+  // idle once the recording runs out
+  let frame = replay.frames.get(cursor.0).copied().unwrap_or_default();
+  cursor.0 += 1;
+
+  controls.move_dir = frame.move_dir;
+  controls.drop = frame.drop;
+  controls.hold = frame.hold;
+  // still let the viewer quit with Esc/Q
+  controls.end_game |= frame.end_game;
+}
+
+fn save_replay(
+  mut replay: ResMut<Persistent<Replay>>,
+  rng: Res<GameRng>,
+  settings: Res<Settings>,
+  mode: Res<GameMode>,
+  levels: Option<Res<Levels>>,
+  current: Option<Res<CurrentLevel>>,
+) {
+  replay.seed = rng.seed;
+  replay.mode = *mode;
+  replay.level = match (*mode, levels, current) {
+    (GameMode::Puzzle, Some(levels), Some(current)) => levels.0.get(current.0).map(|level| level.id.clone()),
+    _ => None,
+  };
+  replay.move_speed = settings.move_speed;
+  if replay.persist().is_ok() {
+    println!("Replay saved: {} frames, seed {}", replay.frames.len(), replay.seed);
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+fn is_recording(recording: Res<Recording>) -> bool {
+  recording.0
+}
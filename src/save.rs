@@ -0,0 +1,253 @@
+use bevy::{prelude::*, window::WindowCloseRequested};
+use bevy_rapier2d::prelude::*;
+use bevy_persistent::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::util::{AppState, Score, Fruit, GameRng, GameOverReason, GameMode, DailyRun, FruitTable};
+use crate::game::{
+  GameSet,
+  Positions,
+  ActiveFruit,
+  FruitQueue,
+  HoldSlot,
+  Chain,
+  ChainLink,
+  GameStats,
+  spawn_active_fruit,
+  spawn_collider_fruit,
+};
+
+// seconds between autosaves while playing
+const AUTOSAVE_INTERVAL: f32 = 5.0;
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+  fn build(&self, app: &mut App) {
+    // no-op with a window, keeps headless apps working
+    app.add_event::<WindowCloseRequested>()
+      .insert_resource(AutoSave(Timer::from_seconds(AUTOSAVE_INTERVAL, TimerMode::Repeating)))
+      .add_systems(OnEnter(AppState::InGame), restore_game
+        .after(GameSet::Reset)
+        .run_if(resource_exists::<ResumeGame>()))
+      // puzzle levels are short and start over, they leave the save alone
+      .add_systems(FixedUpdate, autosave
+        .after(GameSet::Logic)
+        .run_if(in_state(AppState::InGame))
+        .run_if(not(resource_equals(GameMode::Puzzle))))
+      .add_systems(Update, save_on_close
+        .run_if(in_state(AppState::InGame))
+        .run_if(not(resource_equals(GameMode::Puzzle))))
+      // a finished or abandoned game can't be continued
+      .add_systems(OnExit(AppState::InGame), clear_save
+        .run_if(resource_exists::<GameOverReason>())
+        .run_if(not(resource_equals(GameMode::Puzzle))));
+  }
+}
+
+// -- RESOURCES --
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct FruitState {
+  pub id: i32,
+  pub x: f32,
+  pub y: f32,
+  pub z: f32,
+  pub rotation: f32,
+  pub linvel_x: f32,
+  pub linvel_y: f32,
+  pub angvel: f32,
+  // chain the fruit was made in, it can still extend it
+  pub chain_link: Option<u32>,
+}
+
+// board of an unfinished game, rng is None when there is nothing to continue
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SavedGame {
+  pub fruits: Vec<FruitState>,
+  pub active_fruit: Option<(i32, f32)>,
+  pub queue: Vec<i32>,
+  pub hold: HoldSlot,
+  pub chain: Chain,
+  pub stats: GameStats,
+  pub score: i32,
+  pub rng: Option<GameRng>,
+  // the menu switches to these before continuing
+  pub mode: GameMode,
+  pub daily: DailyRun,
+}
+
+impl SavedGame {
+  pub fn can_continue(&self) -> bool {
+    self.rng.is_some()
+  }
+}
+
+// inserted by the menu to continue the saved game instead of starting fresh
+#[derive(Resource)]
+pub struct ResumeGame;
+
+#[derive(Resource)]
+struct AutoSave(Timer);
+
+// each fruit on the board as snapshot reads it
+pub(crate) type BoardFruits<'w, 's> = Query<'w, 's, (
+  &'static Fruit,
+  &'static Transform,
+  &'static Velocity,
+  Option<&'static ChainLink>,
+)>;
+
+// -- SYSTEMS --
+fn restore_game(
+  mut commands: Commands,
+  positions: Res<Positions>,
+  saved: Res<Persistent<SavedGame>>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  mut score: ResMut<Score>,
+  mut rng: ResMut<GameRng>,
+  mut queue: ResMut<FruitQueue>,
+  mut hold: ResMut<HoldSlot>,
+  mut chain: ResMut<Chain>,
+  mut stats: ResMut<GameStats>,
+  table: Res<FruitTable>,
+) {
+  commands.remove_resource::<ResumeGame>();
+  if restore_board(&mut commands, &positions, &mut meshes, &mut materials, &table, &saved,
+    &mut score, &mut rng, &mut queue, &mut hold, &mut chain, &mut stats) {
+    println!("Continuing saved game: {} fruits, score {}", saved.fruits.len(), saved.score);
+  }
+}
+
+fn autosave(
+  mut timer: ResMut<AutoSave>,
+  fixed_time: Res<FixedTime>,
+  mut saved: ResMut<Persistent<SavedGame>>,
+  fruits: BoardFruits,
+  active_fruit: Query<(&ActiveFruit, &Transform)>,
+  queue: Res<FruitQueue>,
+  hold: Res<HoldSlot>,
+  chain: Res<Chain>,
+  stats: Res<GameStats>,
+  score: Res<Score>,
+  rng: Res<GameRng>,
+  mode: Res<GameMode>,
+  daily: Res<DailyRun>,
+) {
+  timer.0.tick(fixed_time.period);
+  if timer.0.just_finished() {
+    saved.set(SavedGame {
+      mode: *mode,
+      daily: *daily,
+      ..snapshot(&fruits, &active_fruit, &queue, &hold, &chain, &stats, &score, &rng)
+    }).ok();
+  }
+}
+
+fn save_on_close(
+  mut close_events: EventReader<WindowCloseRequested>,
+  mut saved: ResMut<Persistent<SavedGame>>,
+  fruits: BoardFruits,
+  active_fruit: Query<(&ActiveFruit, &Transform)>,
+  queue: Res<FruitQueue>,
+  hold: Res<HoldSlot>,
+  chain: Res<Chain>,
+  stats: Res<GameStats>,
+  score: Res<Score>,
+  rng: Res<GameRng>,
+  mode: Res<GameMode>,
+  daily: Res<DailyRun>,
+) {
+  if close_events.iter().next().is_some()
+    && saved.set(SavedGame {
+      mode: *mode,
+      daily: *daily,
+      ..snapshot(&fruits, &active_fruit, &queue, &hold, &chain, &stats, &score, &rng)
+    }).is_ok() {
+    println!("Game saved");
+  }
+}
+
+fn clear_save(mut saved: ResMut<Persistent<SavedGame>>) {
+  saved.set(SavedGame::default()).ok();
+}
+
+// --- HELPER FUNCTIONS ---
+// puts a saved board into the running game, false if there is nothing to restore
+pub(crate) fn restore_board(
+  commands: &mut Commands,
+  positions: &Positions,
+  meshes: &mut ResMut<Assets<Mesh>>,
+  materials: &mut ResMut<Assets<ColorMaterial>>,
+  table: &FruitTable,
+  saved: &SavedGame,
+  score: &mut Score,
+  rng: &mut GameRng,
+  queue: &mut FruitQueue,
+  hold: &mut HoldSlot,
+  chain: &mut Chain,
+  stats: &mut GameStats,
+) -> bool {
+  let Some(saved_rng) = &saved.rng else {
+    return false;
+  };
+  *rng = saved_rng.clone();
+  score.0 = saved.score;
+
+  for fruit in saved.fruits.iter() {
+    let pos = Vec3::new(fruit.x, fruit.y, fruit.z);
+    let e = spawn_collider_fruit(commands, meshes, materials, table, fruit.id, pos);
+    commands.entity(e).insert((
+      Transform::from_translation(pos).with_rotation(Quat::from_rotation_z(fruit.rotation)),
+      Velocity {
+        linvel: Vec2::new(fruit.linvel_x, fruit.linvel_y),
+        angvel: fruit.angvel,
+      },
+    ));
+    if let Some(link) = fruit.chain_link {
+      commands.entity(e).insert(ChainLink(link));
+    }
+  }
+  if let Some((id, x)) = saved.active_fruit {
+    spawn_active_fruit(commands, positions, meshes, materials, table, id, x);
+  }
+  queue.fruits = saved.queue.iter().copied().collect();
+  *hold = saved.hold;
+  *chain = saved.chain;
+  *stats = saved.stats;
+  true
+}
+
+pub(crate) fn snapshot(
+  fruits: &BoardFruits,
+  active_fruit: &Query<(&ActiveFruit, &Transform)>,
+  queue: &FruitQueue,
+  hold: &HoldSlot,
+  chain: &Chain,
+  stats: &GameStats,
+  score: &Score,
+  rng: &GameRng,
+) -> SavedGame {
+  SavedGame {
+    fruits: fruits.iter().map(|(fruit, transform, velocity, link)| FruitState {
+      id: fruit.id,
+      x: transform.translation.x,
+      y: transform.translation.y,
+      z: transform.translation.z,
+      rotation: transform.rotation.to_euler(EulerRot::XYZ).2,
+      linvel_x: velocity.linvel.x,
+      linvel_y: velocity.linvel.y,
+      angvel: velocity.angvel,
+      chain_link: link.map(|l| l.0),
+    }).collect(),
+    active_fruit: active_fruit.get_single().ok().map(|(f, t)| (f.0, t.translation.x)),
+    queue: queue.fruits.iter().copied().collect(),
+    hold: *hold,
+    chain: *chain,
+    stats: *stats,
+    score: score.0,
+    rng: Some(rng.clone()),
+    mode: GameMode::default(),
+    daily: DailyRun::default(),
+  }
+}
@@ -0,0 +1,271 @@
+use bevy::{prelude::*, input::touch::TouchPhase, window::WindowMode};
+use bevy::audio::GlobalVolume;
+use bevy_persistent::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::camera::touch_to_world;
+use crate::util::{AppState, MainCamera, TEXT_COLOR};
+
+// rows on the settings screen, the last one goes back to the menu
+const ROWS: usize = 6;
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<Settings>()
+      .init_resource::<SettingsCursor>()
+      .add_systems(Update, apply_settings.run_if(resource_changed::<Settings>()))
+      .add_systems(OnEnter(AppState::Settings), setup_settings)
+      .add_systems(Update, settings_loop.run_if(in_state(AppState::Settings)))
+      .add_systems(OnExit(AppState::Settings), close_settings);
+  }
+}
+
+// -- RESOURCES --
+// player options, the launcher keeps them in settings.ini
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Settings {
+  pub move_speed: f32, // units per second
+  pub drop_cooldown: f32, // seconds between drops
+  pub volume: f32,
+  #[serde(with = "ini_bool")]
+  pub fullscreen: bool,
+  pub ui_scale: f32,
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    Settings {
+      move_speed: 168.0,
+      drop_cooldown: 0.4,
+      volume: 1.0,
+      fullscreen: false,
+      ui_scale: 1.0,
+    }
+  }
+}
+
+impl Settings {
+  fn label(&self, row: usize) -> String {
+    match row {
+      0 => format!("Move speed: {:.0}", self.move_speed),
+      1 => format!("Drop cooldown: {:.2}s", self.drop_cooldown),
+      2 => format!("Volume: {:.0}%", self.volume * 100.0),
+      3 => format!("Fullscreen: {}", if self.fullscreen { "on" } else { "off" }),
+      4 => format!("UI scale: {:.0}%", self.ui_scale * 100.0),
+      _ => "Back".to_string(),
+    }
+  }
+
+  // steps a row up (dir > 0) or down
+  fn adjust(&mut self, row: usize, dir: f32) {
+    match row {
+      0 => self.move_speed = (self.move_speed + 12.0 * dir).clamp(60.0, 360.0),
+      1 => self.drop_cooldown = (self.drop_cooldown + 0.05 * dir).clamp(0.1, 1.0),
+      2 => self.volume = (self.volume + 0.1 * dir).clamp(0.0, 1.0),
+      3 => self.fullscreen = !self.fullscreen,
+      4 => self.ui_scale = (self.ui_scale + 0.05 * dir).clamp(0.75, 1.25),
+      _ => {},
+    }
+  }
+}
+
+// serde_ini can't write bools, keep them as "true"/"false"
+mod ini_bool {
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(value: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(if *value { "true" } else { "false" })
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    String::deserialize(deserializer).map(|value| value == "true")
+  }
+}
+
+// used instead of Settings::move_speed without touching the saved settings, replays
+// play at the speed they were recorded with
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct MoveSpeedOverride(pub f32);
+
+#[derive(Resource, Default)]
+struct SettingsCursor(usize);
+
+// -- COMPONENTS --
+#[derive(Component)]
+struct SettingsItem;
+
+#[derive(Component)]
+struct SettingsRow(usize);
+
+// -- SYSTEMS --
+fn apply_settings(
+  mut commands: Commands,
+  settings: Res<Settings>,
+  mut windows: Query<&mut Window>,
+) {
+  for mut window in windows.iter_mut() {
+    let mode = if settings.fullscreen {
+      WindowMode::BorderlessFullscreen
+    } else {
+      WindowMode::Windowed
+    };
+    if window.mode != mode {
+      window.mode = mode;
+    }
+  }
+  commands.insert_resource(GlobalVolume::new(settings.volume));
+}
+
+fn setup_settings(
+  mut commands: Commands,
+  settings: Res<Settings>,
+  mut cursor: ResMut<SettingsCursor>,
+) {
+  cursor.0 = 0;
+
+  // title
+  commands.spawn((
+    SettingsItem,
+    Text2dBundle {
+      text: Text::from_section(
+        "Settings",
+        TextStyle {
+          font_size: 60.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ),
+      transform: Transform::from_translation(Vec3::new(0.0, 180.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  // rows
+  for row in 0..ROWS {
+    commands.spawn((
+      SettingsItem,
+      SettingsRow(row),
+      Text2dBundle {
+        text: Text::from_section(
+          row_text(&settings, row, 0),
+          TextStyle {
+            font_size: 30.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+        transform: Transform::from_translation(Vec3::new(0.0, 90.0 - 45.0 * row as f32, 10.0)),
+        ..default()
+      },
+    ));
+  }
+
+  // help text
+  commands.spawn((
+    SettingsItem,
+    Text2dBundle {
+      text: Text::from_section(
+        "Up/Down: select | Left/Right: change | Esc: back\ntap the left or right side of a row to change it",
+        TextStyle {
+          font_size: 20.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(Vec3::new(0.0, -220.0, 10.0)),
+      ..default()
+    },
+  ));
+}
+
+fn settings_loop(
+  keys: Res<Input<KeyCode>>,
+  mut touch_events: EventReader<TouchInput>,
+  camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  mut settings: ResMut<Settings>,
+  mut cursor: ResMut<SettingsCursor>,
+  mut rows: Query<(&mut Text, &SettingsRow, &Transform)>,
+  mut next_state: ResMut<NextState<AppState>>,
+) {
+  let mut adjust = 0.0;
+  if keys.just_pressed(KeyCode::Up) || keys.just_pressed(KeyCode::W) {
+    cursor.0 = (cursor.0 + ROWS - 1) % ROWS;
+  }
+  if keys.just_pressed(KeyCode::Down) || keys.just_pressed(KeyCode::S) {
+    cursor.0 = (cursor.0 + 1) % ROWS;
+  }
+  if keys.just_pressed(KeyCode::Left) || keys.just_pressed(KeyCode::A) {
+    adjust = -1.0;
+  }
+  if keys.just_pressed(KeyCode::Right) || keys.just_pressed(KeyCode::D) {
+    adjust = 1.0;
+  }
+  let mut back = keys.just_pressed(KeyCode::Escape);
+  if keys.just_pressed(KeyCode::Return) || keys.just_pressed(KeyCode::Space) {
+    if cursor.0 == ROWS - 1 {
+      back = true;
+    } else {
+      adjust = 1.0;
+    }
+  }
+
+  // tap a row, left half lowers and right half raises
+  for touch in touch_events.iter() {
+    if touch.phase != TouchPhase::Ended {
+      continue;
+    }
+    let Some(pos) = touch_to_world(&camera, touch.position) else {
+      continue;
+    };
+    for (_, row, transform) in rows.iter() {
+      if (pos.y - transform.translation.y).abs() < 20.0 && pos.x.abs() < 250.0 {
+        cursor.0 = row.0;
+        if row.0 == ROWS - 1 {
+          back = true;
+        } else {
+          adjust = if pos.x < 0.0 { -1.0 } else { 1.0 };
+        }
+      }
+    }
+  }
+
+  if adjust != 0.0 {
+    settings.adjust(cursor.0, adjust);
+  }
+  if back {
+    next_state.set(AppState::Menu);
+  }
+
+  if cursor.is_changed() || settings.is_changed() {
+    for (mut text, row, _) in rows.iter_mut() {
+      text.sections[0].value = row_text(&settings, row.0, cursor.0);
+    }
+  }
+}
+
+fn close_settings(
+  mut commands: Commands,
+  items: Query<Entity, With<SettingsItem>>,
+  settings: Res<Settings>,
+  persistent: Option<ResMut<Persistent<Settings>>>,
+) {
+  for e in items.iter() {
+    commands.entity(e).despawn_recursive();
+  }
+  if let Some(mut persistent) = persistent {
+    if persistent.set(settings.clone()).is_ok() {
+      println!("Settings saved");
+    }
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+fn row_text(settings: &Settings, row: usize, cursor: usize) -> String {
+  if row == cursor {
+    format!("> {} <", settings.label(row))
+  } else {
+    settings.label(row)
+  }
+}
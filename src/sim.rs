@@ -0,0 +1,395 @@
+use std::{collections::VecDeque, time::Duration};
+use bevy::{
+  prelude::*,
+  ecs::system::SystemState,
+  asset::AssetPlugin,
+  input::InputPlugin,
+  time::TimeUpdateStrategy,
+  transform::TransformPlugin,
+  hierarchy::HierarchyPlugin,
+};
+use bevy_rapier2d::prelude::*;
+
+use crate::util::{AppState, Score, Fruit, GameSeed, GameRng, GameOverReason, FruitTable, MIN_SPEED, FIXED_DT, CONTAINER_W};
+use crate::settings::Settings;
+use crate::game::{
+  InGamePlugin,
+  FixedPhysicsPlugin,
+  GameSet,
+  Positions,
+  Controls,
+  ActiveFruit,
+  FruitQueue,
+  HoldSlot,
+  Chain,
+  GameStats,
+  spawn_collider_fruit,
+};
+use crate::save::{SavedGame, BoardFruits, restore_board, snapshot};
+use crate::autoplay::{AutoPlayerPlugin, AutoPlayer, Strategy};
+
+// give up waiting for the board to settle after this many ticks
+const MAX_SETTLE_TICKS: u32 = 600;
+
+// -- RESOURCES --
+#[derive(Resource, Default)]
+pub struct DropScript {
+  drops: VecDeque<f32>,
+  wait: u32,
+  settle_ticks: u32,
+  pub finished: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct SimStats {
+  pub largest_fruit: i32,
+  pub drops: usize,
+}
+
+// board a branch starts from instead of an empty cup
+#[derive(Resource)]
+struct StartFrom(SavedGame);
+
+#[derive(Debug)]
+pub struct SimResult {
+  pub score: i32,
+  pub largest_fruit: i32,
+  pub longest_chain: i32,
+  pub drops: usize,
+  pub reason: Option<GameOverReason>,
+}
+
+// game logic on MinimalPlugins, no window or renderer
+pub fn headless_app(seed: Option<u64>) -> App {
+  headless_app_with(seed, FruitTable::load())
+}
+
+// same, with a tier table the caller already has
+pub fn headless_app_with(seed: Option<u64>, table: FruitTable) -> App {
+  let mut app = App::new();
+  app.add_plugins((
+      MinimalPlugins,
+      AssetPlugin::default(),
+      InputPlugin,
+      TransformPlugin,
+      HierarchyPlugin,
+      FixedPhysicsPlugin,
+    ))
+    .add_asset::<Mesh>()
+    .add_asset::<ColorMaterial>()
+    .add_asset::<Image>()
+    // one fixed tick per update
+    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(FIXED_DT)))
+    .insert_resource(GameSeed(seed))
+    .insert_resource(table)
+    .add_plugins(InGamePlugin);
+  app.finish();
+  app.cleanup();
+  app
+}
+
+// settings that move the active fruit to its spot in a single tick
+pub fn fast_settings() -> Settings {
+  // some cooldown is needed, a fruit that was just let go still counts as settled
+  Settings { move_speed: 1.0e6, drop_cooldown: 0.1, ..default() }
+}
+
+// adds the systems that play a DropScript, safe to call more than once
+pub fn add_drop_script(app: &mut App) {
+  if app.world.contains_resource::<DropScript>() {
+    return;
+  }
+  app.init_resource::<DropScript>()
+    .init_resource::<SimStats>()
+    .add_systems(FixedUpdate, drive_drops
+      .in_set(GameSet::Control)
+      .run_if(in_state(AppState::InGame)))
+    .add_systems(Update, track_largest);
+}
+
+// drops a fruit at each x, waiting for the board to settle in between
+pub fn run_drops(app: &mut App, drops: &[f32]) -> SimResult {
+  add_drop_script(app);
+  app.insert_resource(DropScript { drops: drops.iter().copied().collect(), ..default() });
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+
+  loop {
+    app.update();
+    if app.world.resource::<DropScript>().finished
+      || *app.world.resource::<State<AppState>>().get() == AppState::GameOver {
+      break;
+    }
+  }
+
+  let stats = app.world.resource::<SimStats>();
+  SimResult {
+    score: app.world.resource::<Score>().0,
+    largest_fruit: stats.largest_fruit,
+    longest_chain: app.world.resource::<Chain>().longest,
+    drops: stats.drops,
+    reason: app.world.get_resource::<GameOverReason>().copied(),
+  }
+}
+
+// lets the autoplayer play until game over or max_drops drops
+pub fn run_autoplayer(app: &mut App, strategy: Strategy, max_drops: u32) -> SimResult {
+  app.add_plugins(AutoPlayerPlugin)
+    .insert_resource(AutoPlayer::new(true).with_strategy(strategy))
+    .init_resource::<SimStats>()
+    .add_systems(Update, track_largest);
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+
+  loop {
+    app.update();
+    if app.world.resource::<GameStats>().drops >= max_drops
+      || *app.world.resource::<State<AppState>>().get() == AppState::GameOver {
+      break;
+    }
+  }
+
+  SimResult {
+    score: app.world.resource::<Score>().0,
+    largest_fruit: app.world.resource::<SimStats>().largest_fruit,
+    longest_chain: app.world.resource::<Chain>().longest,
+    drops: app.world.resource::<GameStats>().drops as usize,
+    reason: app.world.get_resource::<GameOverReason>().copied(),
+  }
+}
+
+// puts a resting fruit of tier `id` into a headless game
+pub fn spawn_fruit(app: &mut App, id: i32, position: Vec2) -> Entity {
+  let mut state = SystemState::<(
+    Commands,
+    ResMut<Assets<Mesh>>,
+    ResMut<Assets<ColorMaterial>>,
+    Res<FruitTable>,
+  )>::new(&mut app.world);
+  let (mut commands, mut meshes, mut materials, table) = state.get_mut(&mut app.world);
+  // z 3.5 is the one without spin
+  let e = spawn_collider_fruit(&mut commands, &mut meshes, &mut materials, &table, id, position.extend(3.5));
+  state.apply(&mut app.world);
+  e
+}
+
+// ends a headless game and empties the cup, entering InGame again starts over
+pub fn clear_board(app: &mut App) {
+  if *app.world.resource::<State<AppState>>().get() == AppState::InGame {
+    app.world.resource_mut::<NextState<AppState>>().set(AppState::GameOver);
+    app.update();
+  }
+  let world = &mut app.world;
+  let fruits: Vec<Entity> = world.query_filtered::<Entity, With<Fruit>>().iter(world).collect();
+  for e in fruits {
+    world.entity_mut(e).despawn_recursive();
+  }
+}
+
+// current board of a headless app, same as what SavePlugin writes
+pub fn board_snapshot(app: &mut App) -> SavedGame {
+  let mut state = SystemState::<(
+    BoardFruits,
+    Query<(&ActiveFruit, &Transform)>,
+    Res<FruitQueue>,
+    Res<HoldSlot>,
+    Res<Chain>,
+    Res<GameStats>,
+    Res<Score>,
+    Res<GameRng>,
+  )>::new(&mut app.world);
+  let (fruits, active_fruit, queue, hold, chain, stats, score, rng) = state.get(&app.world);
+  snapshot(&fruits, &active_fruit, &queue, &hold, &chain, &stats, &score, &rng)
+}
+
+// -- LOOKAHEAD --
+// a board after some drops were played out in the sandbox
+pub struct Branch {
+  pub result: SimResult,
+  pub board: SavedGame,
+  pub height: f32, // from the cup base to the highest fruit edge
+  pub headroom: f32, // from that edge to the line
+}
+
+// one headless game that every branch of a search is played in, the board is
+// swapped out between branches. Fruits keep their velocities, rapier starts the
+// contacts between them over
+pub struct Sandbox {
+  app: App,
+}
+
+impl Sandbox {
+  pub fn new(table: &FruitTable) -> Self {
+    let mut app = headless_app_with(None, table.clone());
+    app.insert_resource(fast_settings())
+      .add_systems(OnEnter(AppState::InGame), start_from.after(GameSet::Reset));
+    add_drop_script(&mut app);
+    Sandbox { app }
+  }
+
+  // plays drops from a saved board, the fruits teleport to each spot
+  pub fn play_out(&mut self, start: &SavedGame, drops: &[f32]) -> Branch {
+    self.clear_board();
+    self.app.insert_resource(StartFrom(start.clone()))
+      .insert_resource(SimStats::default());
+    let result = run_drops(&mut self.app, drops);
+    let board = board_snapshot(&mut self.app);
+    let table = self.app.world.resource::<FruitTable>();
+    let positions = self.app.world.resource::<Positions>();
+    let top = board.fruits.iter()
+      .map(|f| f.y + table.get(f.id).size / 2.0)
+      .fold(positions.cup_base_y, f32::max);
+    Branch {
+      result,
+      board,
+      height: top - positions.cup_base_y,
+      headroom: positions.cup_max_y - top,
+    }
+  }
+
+  fn clear_board(&mut self) {
+    clear_board(&mut self.app);
+  }
+}
+
+// picks drops by playing candidates out, then the best `beam` of them again with
+// the next fruit, `depth` fruits deep
+#[derive(Clone, Copy, Debug)]
+pub struct Lookahead {
+  pub candidates: usize,
+  pub depth: usize,
+  pub beam: usize,
+}
+
+impl Default for Lookahead {
+  fn default() -> Self {
+    Lookahead { candidates: 9, depth: 2, beam: 3 }
+  }
+}
+
+impl Lookahead {
+  // x to drop the active fruit of `start` at, keep the sandbox around for the next call
+  pub fn best_drop(&self, sandbox: &mut Sandbox, start: &SavedGame, table: &FruitTable) -> f32 {
+    self.search(sandbox, start, table, self.depth.max(1)).map(|(x, _)| x).unwrap_or(0.0)
+  }
+
+  // best (x, value) for the active fruit, value is the score gained on the way
+  // minus how high the final board is stacked
+  fn search(&self, sandbox: &mut Sandbox, start: &SavedGame, table: &FruitTable, depth: usize) -> Option<(f32, f32)> {
+    let (id, _) = start.active_fruit?;
+    let limit = CONTAINER_W / 2.0 - table.get(id).size / 2.0;
+    let n = self.candidates.max(2);
+    let mut branches: Vec<(f32, f32, Branch)> = (0..n)
+      .map(|i| -limit + 2.0 * limit * i as f32 / (n - 1) as f32)
+      .map(|x| {
+        let branch = sandbox.play_out(start, &[x]);
+        (x, board_value(start, &branch), branch)
+      })
+      .collect();
+    branches.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    if depth > 1 {
+      // only the searched branches compare fairly with each other
+      branches.truncate(self.beam.max(1));
+      for (_, value, branch) in branches.iter_mut() {
+        if branch.result.reason.is_some() {
+          continue;
+        }
+        if let Some((_, next)) = self.search(sandbox, &branch.board, table, depth - 1) {
+          *value = (branch.result.score - start.score) as f32 + next;
+        }
+      }
+    }
+    branches.into_iter()
+      .max_by(|a, b| a.1.total_cmp(&b.1))
+      .map(|(x, value, _)| (x, value))
+  }
+}
+
+// -- SYSTEMS --
+fn start_from(
+  mut commands: Commands,
+  start: Res<StartFrom>,
+  positions: Res<Positions>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  table: Res<FruitTable>,
+  mut score: ResMut<Score>,
+  mut rng: ResMut<GameRng>,
+  mut queue: ResMut<FruitQueue>,
+  mut hold: ResMut<HoldSlot>,
+  mut chain: ResMut<Chain>,
+  mut stats: ResMut<GameStats>,
+) {
+  restore_board(&mut commands, &positions, &mut meshes, &mut materials, &table, &start.0,
+    &mut score, &mut rng, &mut queue, &mut hold, &mut chain, &mut stats);
+}
+
+fn drive_drops(
+  mut script: ResMut<DropScript>,
+  mut stats: ResMut<SimStats>,
+  positions: Res<Positions>,
+  table: Res<FruitTable>,
+  settings: Res<Settings>,
+  mut controls: Query<&mut Controls>,
+  active_fruit: Query<(&Transform, &ActiveFruit)>,
+  fruits: Query<&Velocity, With<Fruit>>,
+) {
+  let Ok(mut controls) = controls.get_single_mut() else {
+    return;
+  };
+  controls.move_dir = 0.0;
+  controls.drop = false;
+
+  // wait out the drop cooldown, then until nothing is moving
+  script.settle_ticks += 1;
+  if script.wait > 0 {
+    script.wait -= 1;
+    return;
+  }
+  let settled = fruits.iter().all(|v| v.linvel.length() < MIN_SPEED);
+  if !settled && script.settle_ticks < MAX_SETTLE_TICKS {
+    return;
+  }
+
+  let Some(&target) = script.drops.front() else {
+    script.finished = true;
+    return;
+  };
+  let Ok((transform, active_fruit)) = active_fruit.get_single() else {
+    return;
+  };
+
+  // steer to target, drop once there
+  let limit = positions.cup_right_x - table.get(active_fruit.0).size / 2.0;
+  let delta = target.clamp(-limit, limit) - transform.translation.x;
+  if delta.abs() < 0.01 {
+    controls.drop = true;
+    script.drops.pop_front();
+    script.wait = (settings.drop_cooldown / FIXED_DT) as u32;
+    script.settle_ticks = 0;
+    stats.drops += 1;
+  } else {
+    controls.move_dir = (delta / (settings.move_speed * FIXED_DT)).clamp(-1.0, 1.0);
+  }
+}
+
+// --- HELPER FUNCTIONS ---
+fn board_value(start: &SavedGame, branch: &Branch) -> f32 {
+  if branch.result.reason.is_some() {
+    return -1.0e6;
+  }
+  let mut value = (branch.result.score - start.score) as f32 - 0.05 * branch.height;
+  // close to the line is worse than the height alone says
+  if branch.headroom < 80.0 {
+    value -= 2.0 * (80.0 - branch.headroom);
+  }
+  value
+}
+
+fn track_largest(
+  mut stats: ResMut<SimStats>,
+  fruits: Query<&Fruit, Added<Fruit>>,
+) {
+  for fruit in fruits.iter() {
+    stats.largest_fruit = stats.largest_fruit.max(fruit.id);
+  }
+}
@@ -1,5 +1,9 @@
+use std::collections::BTreeMap;
 use bevy::prelude::*;
-use serde::{Serialize, Deserialize};
+use rand::{Rng, RngCore, SeedableRng};
+use rand_pcg::Pcg32;
+use chrono::Datelike;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
 // ---- SCENES ----
 #[derive(Debug, Clone, Eq, PartialEq, Hash, States, Default)]
@@ -8,14 +12,492 @@ pub enum AppState {
 	Menu,
 	InGame,
 	GameOver,
+	Settings,
+	Stats,
+	LevelSelect,
+	Versus,
+}
+
+// runs alongside AppState::InGame, the board stays as it is while Paused
+#[derive(Debug, Clone, Eq, PartialEq, Hash, States, Default)]
+pub enum PauseState {
+	#[default]
+	Running,
+	Paused,
 }
 
 // ---- RESOURCES ----
-#[derive(Resource)]
+// rules of the current game, picked on the menu
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GameMode {
+  #[default]
+  Classic,
+  // score as much as possible before the clock runs out
+  TimeAttack,
+  // nothing ends the game, overflowing fruits are removed for a penalty
+  Zen,
+  // same fruits for everyone on a given day, one ranked attempt per day
+  Daily,
+  // levels with a set board, set drops and a goal, see puzzle.rs
+  Puzzle,
+  // two players side by side on one keyboard, see versus.rs
+  Versus,
+}
+
+impl GameMode {
+  pub const ALL: [GameMode; 6] = [
+    GameMode::Classic,
+    GameMode::TimeAttack,
+    GameMode::Zen,
+    GameMode::Daily,
+    GameMode::Puzzle,
+    GameMode::Versus,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      GameMode::Classic => "Classic",
+      GameMode::TimeAttack => "Time Attack",
+      GameMode::Zen => "Zen",
+      GameMode::Daily => "Daily Challenge",
+      GameMode::Puzzle => "Puzzle",
+      GameMode::Versus => "Versus",
+    }
+  }
+
+  // whether a fruit leaving the cup ends the game
+  pub fn overflow_ends_game(&self) -> bool {
+    !matches!(self, GameMode::Zen)
+  }
+
+  // seconds until the game ends on its own
+  pub fn time_limit(&self) -> Option<f32> {
+    match self {
+      GameMode::TimeAttack => Some(TIME_ATTACK_SECONDS),
+      _ => None,
+    }
+  }
+
+  // the menu cycles through ALL
+  pub fn cycle(&self, step: i32) -> GameMode {
+    let n = GameMode::ALL.len() as i32;
+    let i = GameMode::ALL.iter().position(|m| m == self).unwrap_or(0) as i32;
+    GameMode::ALL[(i + step).rem_euclid(n) as usize]
+  }
+}
+
+// (current score, best score this session)
+#[derive(Resource, Default)]
 pub struct Score (pub i32, pub i32);
 
-#[derive(Resource, Debug, Serialize, Deserialize)]
-pub struct HighScore(pub [i32; 8]);
+// one finished game on the leaderboard
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+  pub score: i32,
+  pub name: String,
+  pub timestamp: u64, // unix seconds, 0 when unknown
+  pub duration: f32, // seconds
+  pub drops: u32,
+  pub highest_tier: i32,
+}
+
+// best games first, at most MAX_HIGH_SCORES of them
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HighScore {
+  pub entries: Vec<ScoreEntry>,
+  // prefilled when the next high score asks for a name
+  pub last_name: String,
+}
+
+impl HighScore {
+  pub fn best(&self) -> i32 {
+    self.entries.first().map(|e| e.score).unwrap_or(0)
+  }
+
+  // returns the rank if the entry made the table
+  pub fn insert(&mut self, entry: ScoreEntry) -> Option<usize> {
+    if entry.score <= 0 {
+      return None;
+    }
+    let rank = self.entries.iter().position(|e| entry.score > e.score).unwrap_or(self.entries.len());
+    if rank >= MAX_HIGH_SCORES {
+      return None;
+    }
+    self.entries.insert(rank, entry);
+    self.entries.truncate(MAX_HIGH_SCORES);
+    Some(rank)
+  }
+
+  // save.bin used to hold a bare bincode [i32; 8]
+  pub fn from_legacy(bytes: &[u8]) -> Option<Self> {
+    if bytes.len() != 4 * MAX_HIGH_SCORES {
+      return None;
+    }
+    let entries = bytes.chunks_exact(4)
+      .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+      .filter(|&score| score > 0)
+      .map(|score| ScoreEntry { score, name: "???".to_string(), ..default() })
+      .collect();
+    Some(HighScore { entries, last_name: String::new() })
+  }
+}
+
+// totals over every game played, kept next to the high scores
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LifetimeStats {
+  pub games_played: u32,
+  pub total_score: i64,
+  pub total_drops: u64,
+  pub playtime: f64, // seconds
+  pub merges: Vec<u32>, // by the tier that merged
+  pub top_fruits: u32, // largest tier created
+  pub game_overs: BTreeMap<String, u32>, // by GameOverReason::kind
+}
+
+impl LifetimeStats {
+  pub fn average_score(&self) -> f64 {
+    if self.games_played == 0 {
+      return 0.0;
+    }
+    self.total_score as f64 / self.games_played as f64
+  }
+
+  pub fn add_merge(&mut self, tier: i32) {
+    let tier = tier.max(0) as usize;
+    if self.merges.len() <= tier {
+      self.merges.resize(tier + 1, 0);
+    }
+    self.merges[tier] += 1;
+  }
+}
+
+// a persisted high score table, each mode that keeps scores has its own
+pub trait Leaderboard: Resource + Serialize + DeserializeOwned {
+  fn table(&self) -> &HighScore;
+  fn table_mut(&mut self) -> &mut HighScore;
+}
+
+impl Leaderboard for HighScore {
+  fn table(&self) -> &HighScore {
+    self
+  }
+
+  fn table_mut(&mut self) -> &mut HighScore {
+    self
+  }
+}
+
+// time attack scores, kept apart from the classic table
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TimeAttackHighScore(pub HighScore);
+
+impl Leaderboard for TimeAttackHighScore {
+  fn table(&self) -> &HighScore {
+    &self.0
+  }
+
+  fn table_mut(&mut self) -> &mut HighScore {
+    &mut self.0
+  }
+}
+
+// daily challenge scores of a single day, other days had other fruits
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DailyHighScore {
+  pub day: i64,
+  pub scores: HighScore,
+}
+
+impl DailyHighScore {
+  // empties the table when day is a new one, false if it already was for day
+  pub fn start_day(&mut self, day: i64) -> bool {
+    if self.day == day {
+      return false;
+    }
+    self.day = day;
+    self.scores.entries.clear();
+    true
+  }
+}
+
+impl Leaderboard for DailyHighScore {
+  fn table(&self) -> &HighScore {
+    &self.scores
+  }
+
+  fn table_mut(&mut self) -> &mut HighScore {
+    &mut self.scores
+  }
+}
+
+// the daily challenge being played, set when the game starts (and saved with it)
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyRun {
+  pub day: i64,
+  // today's attempt was already used, the score isn't kept
+  pub practice: bool,
+}
+
+// which days the daily challenge was played, for the one attempt rule and the streak
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DailyProgress {
+  pub last_day: Option<i64>,
+  pub streak: u32,
+  pub best_streak: u32,
+  pub days_played: u32,
+}
+
+impl DailyProgress {
+  pub fn played(&self, day: i64) -> bool {
+    self.last_day.is_some_and(|last| last >= day)
+  }
+
+  // uses up the attempt for day, false if it already was
+  pub fn attempt(&mut self, day: i64) -> bool {
+    if self.played(day) {
+      return false;
+    }
+    if self.last_day == Some(day - 1) {
+      self.streak += 1;
+    } else {
+      self.streak = 1;
+    }
+    self.last_day = Some(day);
+    self.best_streak = self.best_streak.max(self.streak);
+    self.days_played += 1;
+    true
+  }
+
+  // 0 once a day has been missed
+  pub fn current_streak(&self, today: i64) -> u32 {
+    if self.last_day.is_some_and(|last| last >= today - 1) {
+      self.streak
+    } else {
+      0
+    }
+  }
+}
+
+// set while the game over screen asks for a name for this rank
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct NewHighScore(pub usize);
+
+const BUILTIN_FRUITS: &str = include_str!("../assets/fruits.ron");
+
+// tier chain used by the game, loaded from assets/fruits.ron (a copy is built in
+// for wasm and installs without the file)
+#[derive(Resource, Clone, Debug)]
+pub struct FruitTable {
+  pub fruits: Vec<Fruit>,
+  // new fruits are picked from the first spawn_tiers tiers
+  pub spawn_tiers: i32,
+  pub texture_paths: Vec<Option<String>>,
+  pub textures: Vec<Option<Handle<Image>>>,
+}
+
+#[derive(Deserialize)]
+struct FruitTableFile {
+  spawn_tiers: i32,
+  tiers: Vec<TierDef>,
+}
+
+#[derive(Deserialize)]
+struct TierDef {
+  size: f32,
+  score: i32,
+  color: (f32, f32, f32),
+  #[serde(default)]
+  texture: Option<String>,
+}
+
+impl FruitTable {
+  pub fn from_ron(ron_str: &str) -> Result<Self, String> {
+    let file: FruitTableFile = ron::from_str(ron_str).map_err(|e| e.to_string())?;
+    let n = file.tiers.len();
+    // the cup ui is sized for the largest spawn tier and the one it merges into
+    if file.spawn_tiers < 1 || file.spawn_tiers as usize >= n {
+      return Err(format!("{} tiers can't spawn the first {}, it needs at least one more", n, file.spawn_tiers));
+    }
+    Ok(FruitTable {
+      fruits: file.tiers.iter().enumerate().map(|(i, t)| {
+        Fruit::new(i as i32, t.size, t.score, Color::rgb(t.color.0, t.color.1, t.color.2))
+      }).collect(),
+      spawn_tiers: file.spawn_tiers,
+      texture_paths: file.tiers.into_iter().map(|t| t.texture).collect(),
+      textures: vec![None; n],
+    })
+  }
+
+  // reads assets/fruits.ron, the built-in copy when there is none. A broken file
+  // is an error, playing a different table than the one edited would hide it
+  pub fn load() -> Self {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      let path = bevy::asset::FileAssetIo::get_base_path().join("assets/fruits.ron");
+      if let Ok(ron_str) = std::fs::read_to_string(&path) {
+        return FruitTable::from_ron(&ron_str)
+          .unwrap_or_else(|e| panic!("Err: could not load fruit table {}: {}", path.display(), e));
+      }
+    }
+    FruitTable::builtin()
+  }
+
+  pub fn builtin() -> Self {
+    FruitTable::from_ron(BUILTIN_FRUITS).expect("Err: built-in fruit table is broken")
+  }
+
+  // clamps to the largest tier
+  pub fn get(&self, id: i32) -> Fruit {
+    self.fruits[(id.max(0) as usize).min(self.fruits.len() - 1)]
+  }
+
+  pub fn len(&self) -> usize {
+    self.fruits.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.fruits.is_empty()
+  }
+
+  // largest tier, these no longer merge
+  pub fn top_id(&self) -> i32 {
+    self.fruits.len() as i32 - 1
+  }
+
+  // largest tier new fruits spawn as, the next/hold areas are sized for it
+  pub fn largest_spawn_id(&self) -> i32 {
+    self.spawn_tiers - 1
+  }
+
+  pub fn material(&self, id: i32) -> ColorMaterial {
+    let fruit = self.get(id);
+    ColorMaterial {
+      color: fruit.color,
+      texture: self.textures.get(fruit.id as usize).cloned().flatten(),
+    }
+  }
+}
+
+impl Default for FruitTable {
+  fn default() -> Self {
+    FruitTable::builtin()
+  }
+}
+
+// why the last game ended, removed when a new game starts
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub enum GameOverReason {
+  Quit,
+  TimeUp,
+  // puzzle levels end when the goal is met or can't be anymore
+  LevelComplete,
+  OutOfDrops,
+  LeftBoundary(OffendingFruit),
+  RightBoundary(OffendingFruit),
+  MaxHeight(OffendingFruit),
+  // versus, the other player overflowed first
+  Won,
+}
+
+// the fruit that ended the game, where it was at that moment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OffendingFruit {
+  pub entity: Entity,
+  pub id: i32,
+  pub position: Vec2,
+}
+
+impl GameOverReason {
+  // short name for counting game overs by cause
+  pub fn kind(&self) -> &'static str {
+    match self {
+      GameOverReason::Quit => "quit",
+      GameOverReason::TimeUp => "time up",
+      GameOverReason::LevelComplete => "level complete",
+      GameOverReason::OutOfDrops => "out of drops",
+      GameOverReason::LeftBoundary(_) => "left wall",
+      GameOverReason::RightBoundary(_) => "right wall",
+      GameOverReason::MaxHeight(_) => "over the line",
+      GameOverReason::Won => "won",
+    }
+  }
+
+  pub fn fruit(&self) -> Option<OffendingFruit> {
+    match self {
+      GameOverReason::Quit
+        | GameOverReason::TimeUp
+        | GameOverReason::LevelComplete
+        | GameOverReason::OutOfDrops
+        | GameOverReason::Won => None,
+      GameOverReason::LeftBoundary(fruit)
+        | GameOverReason::RightBoundary(fruit)
+        | GameOverReason::MaxHeight(fruit) => Some(*fruit),
+    }
+  }
+}
+
+impl std::fmt::Display for GameOverReason {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      GameOverReason::Quit => write!(f, "quit by player"),
+      GameOverReason::TimeUp => write!(f, "time ran out"),
+      GameOverReason::LevelComplete => write!(f, "level complete"),
+      GameOverReason::OutOfDrops => write!(f, "ran out of drops before reaching the goal"),
+      GameOverReason::LeftBoundary(fruit) => write!(f, "fruit #{} went past the left wall at ({:.0}, {:.0})",
+        fruit.id, fruit.position.x, fruit.position.y),
+      GameOverReason::RightBoundary(fruit) => write!(f, "fruit #{} went past the right wall at ({:.0}, {:.0})",
+        fruit.id, fruit.position.x, fruit.position.y),
+      GameOverReason::MaxHeight(fruit) => write!(f, "fruit #{} stayed above the line at ({:.0}, {:.0})",
+        fruit.id, fruit.position.x, fruit.position.y),
+      GameOverReason::Won => write!(f, "the other player overflowed first"),
+    }
+  }
+}
+
+// seed used for the next game, picks a fresh one if None
+#[derive(Resource, Default)]
+pub struct GameSeed(pub Option<u64>);
+
+// single source of randomness for gameplay, re-seeded on every new game
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+pub struct GameRng {
+  pub seed: u64,
+  rng: Pcg32,
+}
+
+impl GameRng {
+  pub fn new(seed: u64) -> Self {
+    GameRng { seed, rng: Pcg32::seed_from_u64(seed) }
+  }
+
+  pub fn from_entropy() -> Self {
+    GameRng::new(rand::thread_rng().gen())
+  }
+}
+
+impl Default for GameRng {
+  fn default() -> Self {
+    GameRng::from_entropy()
+  }
+}
+
+impl RngCore for GameRng {
+  fn next_u32(&mut self) -> u32 {
+    self.rng.next_u32()
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    self.rng.next_u64()
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    self.rng.fill_bytes(dest)
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+    self.rng.try_fill_bytes(dest)
+  }
+}
 
 // ---- COMPONENTS ----
 #[derive(Component)]
@@ -40,6 +522,55 @@ impl Fruit {
 	}
 }
 
+// ---- HELPERS ----
+// unix seconds, 0 where there is no system clock
+pub fn now_timestamp() -> u64 {
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map(|d| d.as_secs())
+      .unwrap_or(0)
+  }
+  #[cfg(target_arch = "wasm32")]
+  {
+    0
+  }
+}
+
+// YYYY-MM-DD (UTC) of a unix timestamp, "-" when unknown
+pub fn format_date(timestamp: u64) -> String {
+  if timestamp == 0 {
+    return "-".to_string();
+  }
+  chrono::DateTime::from_timestamp(timestamp as i64, 0)
+    .map(|time| time.date_naive().to_string())
+    .unwrap_or_else(|| "-".to_string())
+}
+
+// today on the local calendar, counted in days
+pub fn today() -> i64 {
+  chrono::Local::now().date_naive().num_days_from_ce() as i64
+}
+
+// YYYY-MM-DD of a day from today()
+pub fn format_day(day: i64) -> String {
+  chrono::NaiveDate::from_num_days_from_ce_opt(day as i32)
+    .map(|date| date.to_string())
+    .unwrap_or_else(|| "-".to_string())
+}
+
+// the same for everyone playing that day, kept away from small --seed values
+pub fn daily_seed(day: i64) -> u64 {
+  (day as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ 0xDA11
+}
+
+// m:ss
+pub fn format_duration(seconds: f32) -> String {
+  let seconds = seconds.max(0.0) as u32;
+  format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
 // ---- CONSTANTS ----
 // sizing
 pub const SCREEN_W: f32 = 1120.0;
@@ -52,9 +583,16 @@ pub const CONTAINER_P: f32 = 25.0;
 // positions
 pub const HOLD_POS: Vec3 = Vec3::new(400.0, 200.0, 0.0);
 pub const HOLD_POS_FRUIT: Vec3 = Vec3::new(400.0, 200.0, 0.5);
+pub const SWAP_POS: Vec3 = Vec3::new(265.0, 200.0, 0.0);
+pub const SWAP_POS_FRUIT: Vec3 = Vec3::new(265.0, 200.0, 0.5);
+pub const QUEUE_POS: Vec3 = Vec3::new(515.0, 240.0, 0.5);
+pub const PAUSE_BUTTON_POS: Vec3 = Vec3::new(530.0, 295.0, 10.0);
+pub const CLOCK_POS: Vec3 = Vec3::new(-400.0, 75.0, 10.0);
 pub const LEGEND_POS: Vec3 = Vec3::new(400.0, -80.0, 0.0);
 
 // physics
+pub const FIXED_DT: f32 = 1.0 / 60.0;
+pub const PIXELS_PER_METER: f32 = 100.0;
 pub const GRAVITY: f32 = 8.0;
 pub const DAMPENING: f32 = 1.8;
 pub const RESTITUATION: f32 = 0.1;
@@ -70,18 +608,15 @@ pub const CONTAINER_COLOR: Color = Color::rgb(0.24, 0.42, 0.33);
 pub const MAX_H_COLOR: Color = Color::rgba(1.0, 0.2, 0.2, 0.8);
 
 // game objects
-pub const CLICK_DELAY: f32 = 0.4;
-pub const MOVE_SPEED: f32 = 2.8;
-pub const SUIKA: [Fruit; 11] = [
-  Fruit::new(0, 33.8, 0, Color::rgb(0.3373, 0.5686, 0.7843)),
-  Fruit::new(1, 38.3, 1, Color::rgb(0.3804, 0.5373, 0.7922)),
-  Fruit::new(2, 54.2, 3, Color::rgb(0.4431, 0.5059, 0.7882)),
-  Fruit::new(3, 63.3, 6, Color::rgb(0.5137, 0.4667, 0.7686)),
-  Fruit::new(4, 80.2, 10, Color::rgb(0.5882, 0.4196, 0.7333)),
-  Fruit::new(5, 102.7, 15, Color::rgb(0.6549, 0.3686, 0.6824)),
-  Fruit::new(6, 116.3, 21, Color::rgb(0.7098, 0.3137, 0.6196)),
-  Fruit::new(7, 137.8, 28, Color::rgb(0.7569, 0.2549, 0.5373)),
-  Fruit::new(8, 159.2, 36, Color::rgb(0.7843, 0.1922, 0.4471)),
-  Fruit::new(9, 197.6, 45, Color::rgb(0.7922, 0.1412, 0.3490)),
-  Fruit::new(10, 235.0, 55, Color::rgb(0.7843, 0.1176, 0.2431)),
-];
\ No newline at end of file
+pub const PREVIEW_COUNT: usize = 3;
+pub const CHAIN_WINDOW: f32 = 0.75; // seconds between merges that still chain
+pub const CHAIN_LINK_WINDOW: f32 = 3.0; // same, when a merge product merges again
+pub const MAX_MULTIPLIER: i32 = 5;
+pub const MAX_HIGH_SCORES: usize = 8;
+pub const MAX_NAME_LEN: usize = 12;
+pub const TIME_ATTACK_SECONDS: f32 = 180.0;
+pub const ZEN_PENALTY: i32 = 10; // per fruit removed for overflowing
+pub const LINE_TIMEOUT: f32 = 0.5; // seconds a fruit can rest over the line
+pub const VERSUS_OFFSET: f32 = 280.0; // each cup's distance from the middle of the screen
+pub const GARBAGE_TIER: i32 = 5; // merging into this tier or above attacks the other player
+pub const GARBAGE_INTERVAL: u32 = 8; // ticks between garbage fruits falling in
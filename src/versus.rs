@@ -0,0 +1,600 @@
+use std::time::Duration;
+use bevy::{
+  prelude::*,
+  input::touch::TouchPhase,
+  sprite::MaterialMesh2dBundle,
+};
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+
+use crate::util::{
+  AppState,
+  Fruit,
+  FruitTable,
+  CoolDown,
+  GameRng,
+  GameSeed,
+  GameOverReason,
+  SCREEN_W,
+  SCREEN_H,
+  TEXT_COLOR,
+  OVERLAY_COLOR,
+  LINE_TIMEOUT,
+  VERSUS_OFFSET,
+  GARBAGE_TIER,
+  GARBAGE_INTERVAL,
+};
+use crate::settings::Settings;
+use crate::game::{
+  GameSet,
+  Cup,
+  PermUIComponent,
+  Positions,
+  Controls,
+  ActiveFruit,
+  FruitQueue,
+  HoldSlot,
+  Chain,
+  GameStats,
+  OnBoard,
+  PendingCollisions,
+  FruitDropped,
+  FruitMerged,
+  GameOver,
+  MergeFruits,
+  latch_inputs,
+  spawn_cup_at,
+  spawn_collider_fruit,
+  control_active_fruit,
+  take_merges,
+  merge_fruits,
+  fruit_overflow,
+};
+
+pub struct VersusPlugin;
+
+impl Plugin for VersusPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<VersusBindings>()
+      .add_systems(OnEnter(AppState::Versus), setup_versus)
+      .add_systems(Update, (
+          handle_versus_inputs.in_set(GameSet::Input),
+          versus_loop,
+          update_versus_hud,
+        ).run_if(in_state(AppState::Versus)))
+      .add_systems(FixedUpdate, latch_inputs
+        .in_set(GameSet::Input)
+        .run_if(in_state(AppState::Versus)))
+      // garbage draws from the match rng, so these run in a fixed order too
+      .add_systems(FixedUpdate, (
+          handle_board_fruits,
+          merge_board_fruits,
+          drop_garbage,
+          track_board_stats,
+          check_overflow,
+        ).chain()
+        .in_set(GameSet::Logic)
+        .run_if(in_state(AppState::Versus))
+        .run_if(|versus: Option<Res<VersusMatch>>| versus.is_some_and(|v| !v.over)))
+      .add_systems(OnExit(AppState::Versus), cleanup_versus);
+  }
+}
+
+// -- RESOURCES --
+// one player's keys
+#[derive(Clone, Copy, Debug)]
+pub struct PlayerBindings {
+  pub left: KeyCode,
+  pub right: KeyCode,
+  pub drop: KeyCode,
+  pub hold: KeyCode,
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct VersusBindings(pub [PlayerBindings; 2]);
+
+impl Default for VersusBindings {
+  fn default() -> Self {
+    VersusBindings([
+      PlayerBindings { left: KeyCode::A, right: KeyCode::D, drop: KeyCode::S, hold: KeyCode::W },
+      PlayerBindings { left: KeyCode::Left, right: KeyCode::Right, drop: KeyCode::Down, hold: KeyCode::Up },
+    ])
+  }
+}
+
+// the match being played, both boards and the garbage draw from its seed
+#[derive(Resource)]
+pub struct VersusMatch {
+  pub seed: u64,
+  pub over: bool,
+  // None on a draw
+  pub winner: Option<usize>,
+  // why each player's game ended
+  pub reasons: [Option<GameOverReason>; 2],
+  rng: GameRng,
+}
+
+impl VersusMatch {
+  pub fn new(seed: u64) -> Self {
+    // garbage gets its own stream, apart from the boards' fruits
+    VersusMatch { seed, over: false, winner: None, reasons: [None; 2], rng: GameRng::new(!seed) }
+  }
+}
+
+// -- COMPONENTS --
+// one player's cup, with what the single player game keeps in resources.
+// Lives on the entity holding that player's Controls
+#[derive(Component)]
+pub struct VersusBoard {
+  pub player: usize,
+  pub positions: Positions,
+  pub score: i32,
+  pub queue: FruitQueue,
+  pub hold: HoldSlot,
+  pub chain: Chain,
+  pub stats: GameStats,
+  // garbage fruits still to fall in
+  pub incoming: u32,
+  wait: u32,
+  rng: GameRng,
+  // seconds a settled fruit has been over the line
+  over_line: f32,
+}
+
+#[derive(Component, Clone)]
+struct VersusItem;
+
+#[derive(Component)]
+struct VersusHud(usize);
+
+#[derive(Component)]
+struct VersusResult;
+
+// the cup and side panels of the normal game, hidden while versus is on
+type SinglePlayerBoard = Or<(With<Cup>, With<PermUIComponent>)>;
+
+// what a rematch clears away, the cups and keys stay
+type MatchItem = Or<(With<VersusBoard>, With<OnBoard>, With<VersusResult>)>;
+
+// all versus leaves behind, fruits included
+type VersusEntity = Or<(With<VersusItem>, With<OnBoard>)>;
+
+// -- SYSTEMS --
+fn setup_versus(
+  mut commands: Commands,
+  positions: Res<Positions>,
+  seed: Res<GameSeed>,
+  settings: Res<Settings>,
+  bindings: Res<VersusBindings>,
+  mut rapier_config: ResMut<RapierConfiguration>,
+  mut single_player: Query<&mut Visibility, SinglePlayerBoard>,
+  cup_colliders: Query<Entity, (With<Cup>, With<Collider>)>,
+) {
+  // swap the single player cup and panels for one cup per player
+  for mut visibility in single_player.iter_mut() {
+    *visibility = Visibility::Hidden;
+  }
+  for e in cup_colliders.iter() {
+    commands.entity(e).insert(ColliderDisabled);
+  }
+  for player in 0..2 {
+    let board_positions = positions_for(&positions, player);
+    spawn_cup_at(&mut commands, &board_positions, VersusItem);
+    commands.spawn((
+      VersusItem,
+      VersusHud(player),
+      Text2dBundle {
+        text: Text::from_section(
+          "",
+          TextStyle {
+            font_size: 22.0,
+            color: TEXT_COLOR,
+            ..default()
+          },
+        ),
+        transform: Transform::from_translation(Vec3::new(board_offset(player), -303.0, 10.0)),
+        ..default()
+      },
+    ));
+  }
+
+  // keys, in the gap between the cups
+  let keys = bindings.0.iter().enumerate().map(|(player, b)| {
+    format!("P{}\n{:?}/{:?} move\n{:?} drop\n{:?} hold", player + 1, b.left, b.right, b.drop, b.hold)
+  }).collect::<Vec<_>>();
+  commands.spawn((
+    VersusItem,
+    Text2dBundle {
+      text: Text::from_section(
+        keys.join("\n\n") + "\n\nEsc: menu",
+        TextStyle {
+          font_size: 16.0,
+          color: TEXT_COLOR,
+          ..default()
+        },
+      ).with_alignment(TextAlignment::Center),
+      transform: Transform::from_translation(Vec3::new(0.0, 0.0, 10.0)),
+      ..default()
+    },
+  ));
+
+  start_match(&mut commands, &positions, &seed, &settings);
+  rapier_config.physics_pipeline_active = true;
+}
+
+// each player's keys drive their own board's controls
+fn handle_versus_inputs(
+  mut boards: Query<(&VersusBoard, &mut Controls, &mut CoolDown)>,
+  keys: Res<Input<KeyCode>>,
+  bindings: Res<VersusBindings>,
+  time: Res<Time>,
+) {
+  for (board, mut controls, mut cooldown) in boards.iter_mut() {
+    let binding = bindings.0[board.player];
+    cooldown.timer.tick(time.delta());
+    if cooldown.timer.just_finished() {
+      controls.drop_lock = false;
+    }
+    if !controls.drop_lock && keys.just_pressed(binding.drop) {
+      controls.drop_queued = true;
+      controls.drop_lock = true;
+      cooldown.timer.reset();
+    }
+    if keys.just_pressed(binding.hold) {
+      controls.hold_queued = true;
+    }
+    let mut move_dir = 0.0;
+    if keys.pressed(binding.left) {
+      move_dir -= 1.0;
+    }
+    if keys.pressed(binding.right) {
+      move_dir += 1.0;
+    }
+    controls.move_dir = move_dir;
+  }
+}
+
+// handle_active_fruit for each board
+fn handle_board_fruits(
+  mut commands: Commands,
+  fixed_time: Res<FixedTime>,
+  mut boards: Query<(&mut VersusBoard, &Controls)>,
+  mut active_fruits: Query<(Entity, &mut Transform, &ActiveFruit, &OnBoard)>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  table: Res<FruitTable>,
+  settings: Res<Settings>,
+  mut dropped: EventWriter<FruitDropped>,
+) {
+  let step = settings.move_speed * fixed_time.period.as_secs_f32();
+  for (mut board, input) in boards.iter_mut() {
+    let board = &mut *board;
+    let active = active_fruits.iter_mut()
+      .find(|(.., on_board)| on_board.0 == board.player)
+      .map(|(entity, transform, active_fruit, _)| (entity, transform, active_fruit));
+    let drop = control_active_fruit(
+      &mut commands, &mut meshes, &mut materials, &table, &board.positions,
+      &mut board.queue, &mut board.hold, &mut board.rng,
+      input, active, step, Some(board.player),
+    );
+    if let Some(drop) = drop {
+      dropped.send(drop);
+    }
+  }
+}
+
+// handle_merging for each board, big merges queue garbage for the other player
+fn merge_board_fruits(
+  mut commands: Commands,
+  mut collisions: ResMut<PendingCollisions>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  fruits: MergeFruits,
+  mut boards: Query<&mut VersusBoard>,
+  table: Res<FruitTable>,
+  fixed_time: Res<FixedTime>,
+  mut merged: EventWriter<FruitMerged>,
+) {
+  for mut board in boards.iter_mut() {
+    board.chain.since_merge += fixed_time.period.as_secs_f32();
+  }
+
+  let mut sent = [0; 2];
+  for pair in take_merges(&mut collisions, &fruits, &table) {
+    let Some(mut board) = boards.iter_mut().find(|b| pair[0].4.is_some_and(|on_board| on_board.0 == b.player)) else {
+      continue;
+    };
+    let board = &mut *board;
+    let merge = merge_fruits(&mut commands, &mut meshes, &mut materials, &table, pair, &mut board.chain, &mut board.rng);
+    board.score += merge.score;
+    sent[board.player] += garbage_for(merge.output_id);
+    merged.send(merge);
+  }
+
+  for mut board in boards.iter_mut() {
+    board.incoming += sent[1 - board.player];
+  }
+}
+
+// one #0 at a time, somewhere along the top of the cup
+fn drop_garbage(
+  mut commands: Commands,
+  mut versus: ResMut<VersusMatch>,
+  mut boards: Query<&mut VersusBoard>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  table: Res<FruitTable>,
+) {
+  for mut board in boards.iter_mut() {
+    if board.incoming == 0 {
+      continue;
+    }
+    if board.wait > 0 {
+      board.wait -= 1;
+      continue;
+    }
+    let r = table.get(0).size / 2.0;
+    let positions = &board.positions;
+    let x = versus.rng.gen_range(positions.cup_left_x + r..positions.cup_right_x - r);
+    let position = Vec3::new(x, positions.cup_max_y - r, versus.rng.gen_range(2.0..5.0));
+    let e = spawn_collider_fruit(&mut commands, &mut meshes, &mut materials, &table, 0, position);
+    commands.entity(e).insert(OnBoard(board.player));
+    board.incoming -= 1;
+    board.wait = GARBAGE_INTERVAL;
+  }
+}
+
+// track_game_stats for each board
+fn track_board_stats(
+  fixed_time: Res<FixedTime>,
+  mut boards: Query<&mut VersusBoard>,
+  mut dropped: EventReader<FruitDropped>,
+  mut merged: EventReader<FruitMerged>,
+) {
+  for mut board in boards.iter_mut() {
+    board.stats.duration += fixed_time.period.as_secs_f32();
+  }
+  for drop in dropped.iter() {
+    if let Some(mut board) = boards.iter_mut().find(|b| drop.player == Some(b.player)) {
+      board.stats.drops += 1;
+      board.stats.highest_tier = board.stats.highest_tier.max(drop.id);
+    }
+  }
+  for merge in merged.iter() {
+    if let Some(mut board) = boards.iter_mut().find(|b| merge.player == Some(b.player)) {
+      board.stats.highest_tier = board.stats.highest_tier.max(merge.output_id);
+    }
+  }
+}
+
+// end_game for each board, the first player to overflow loses
+fn check_overflow(
+  mut versus: ResMut<VersusMatch>,
+  mut boards: Query<&mut VersusBoard>,
+  fruits: Query<(Entity, &Transform, &Velocity, &Fruit, &OnBoard)>,
+  fixed_time: Res<FixedTime>,
+  mut rapier_config: ResMut<RapierConfiguration>,
+  mut game_over: EventWriter<GameOver>,
+) {
+  let mut lost = [None; 2];
+  for mut board in boards.iter_mut() {
+    let mut over_line = None;
+    for (entity, transform, velocity, fruit, _) in fruits.iter().filter(|(.., on_board)| on_board.0 == board.player) {
+      match fruit_overflow(&board.positions, entity, transform, velocity, fruit) {
+        Some(GameOverReason::MaxHeight(offender)) => over_line = over_line.or(Some(GameOverReason::MaxHeight(offender))),
+        overflow => lost[board.player] = lost[board.player].or(overflow),
+      }
+    }
+    // same grace as the single player game before a fruit over the line ends it
+    board.over_line = if over_line.is_some() { board.over_line + fixed_time.period.as_secs_f32() } else { 0.0 };
+    if board.over_line >= LINE_TIMEOUT {
+      lost[board.player] = lost[board.player].or(over_line);
+    }
+  }
+  if lost.iter().all(Option::is_none) {
+    return;
+  }
+
+  versus.over = true;
+  versus.winner = match lost {
+    [None, Some(_)] => Some(0),
+    [Some(_), None] => Some(1),
+    _ => None,
+  };
+  versus.reasons = lost.map(|reason| Some(reason.unwrap_or(GameOverReason::Won)));
+  announce_results(versus.reasons, boards.iter(), &mut game_over);
+  // freeze both cups under the result
+  rapier_config.physics_pipeline_active = false;
+}
+
+fn update_versus_hud(
+  boards: Query<&VersusBoard>,
+  mut hud: Query<(&mut Text, &VersusHud)>,
+) {
+  for (mut text, player) in hud.iter_mut() {
+    let Some(board) = boards.iter().find(|b| b.player == player.0) else {
+      continue;
+    };
+    let next = board.queue.fruits.front().map(|id| format!("#{}", id)).unwrap_or_default();
+    let hold = board.hold.fruit.map(|id| format!("#{}", id)).unwrap_or("-".to_string());
+    let incoming = if board.incoming > 0 { format!("  +{} incoming", board.incoming) } else { String::new() };
+    text.sections[0].value = format!("P{}: {}   next {}  hold {}{}", player.0 + 1, board.score, next, hold, incoming);
+  }
+}
+
+// Esc leaves, enter or a tap starts a rematch once someone has lost
+fn versus_loop(
+  mut commands: Commands,
+  keys: Res<Input<KeyCode>>,
+  mut touch_events: EventReader<TouchInput>,
+  mut next_state: ResMut<NextState<AppState>>,
+  versus: Res<VersusMatch>,
+  positions: Res<Positions>,
+  seed: Res<GameSeed>,
+  settings: Res<Settings>,
+  mut rapier_config: ResMut<RapierConfiguration>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  match_items: Query<Entity, MatchItem>,
+  result: Query<(), With<VersusResult>>,
+) {
+  if keys.just_pressed(KeyCode::Escape) {
+    next_state.set(AppState::Menu);
+    return;
+  }
+  if !versus.over {
+    return;
+  }
+
+  if result.is_empty() {
+    let title = match versus.winner {
+      Some(player) => format!("Player {} wins!", player + 1),
+      None => "Draw!".to_string(),
+    };
+    // how each player lost
+    let details = versus.reasons.iter().enumerate()
+      .filter_map(|(player, reason)| reason.filter(|r| *r != GameOverReason::Won).map(|r| format!("P{}: {}\n", player + 1, r)))
+      .collect::<String>();
+    commands.spawn((
+      VersusItem,
+      VersusResult,
+      MaterialMesh2dBundle {
+        mesh: meshes.add(shape::Quad::new(Vec2::new(SCREEN_W * 2.0, SCREEN_H * 2.0)).into()).into(),
+        material: materials.add(ColorMaterial::from(OVERLAY_COLOR)),
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 8.0)),
+        ..default()
+      },
+    ));
+    commands.spawn((
+      VersusItem,
+      VersusResult,
+      Text2dBundle {
+        text: Text::from_sections([
+          TextSection::new(
+            title + "\n",
+            TextStyle {
+              font_size: 60.0,
+              color: TEXT_COLOR,
+              ..default()
+            },
+          ),
+          TextSection::new(
+            details,
+            TextStyle {
+              font_size: 20.0,
+              color: TEXT_COLOR,
+              ..default()
+            },
+          ),
+          TextSection::new(
+            "enter or tap for a rematch, Esc for the menu",
+            TextStyle {
+              font_size: 24.0,
+              color: TEXT_COLOR,
+              ..default()
+            },
+          ),
+        ]).with_alignment(TextAlignment::Center),
+        transform: Transform::from_translation(Vec3::new(0.0, 40.0, 10.0)),
+        ..default()
+      },
+    ));
+    return;
+  }
+
+  let tapped = touch_events.iter().any(|t| t.phase == TouchPhase::Ended);
+  if keys.just_pressed(KeyCode::Return) || tapped {
+    for e in match_items.iter() {
+      commands.entity(e).despawn_recursive();
+    }
+    start_match(&mut commands, &positions, &seed, &settings);
+    rapier_config.physics_pipeline_active = true;
+  }
+}
+
+fn cleanup_versus(
+  mut commands: Commands,
+  items: Query<Entity, VersusEntity>,
+  mut single_player: Query<&mut Visibility, SinglePlayerBoard>,
+  cup_colliders: Query<Entity, (With<Cup>, With<Collider>)>,
+  mut rapier_config: ResMut<RapierConfiguration>,
+  versus: Option<Res<VersusMatch>>,
+  boards: Query<&VersusBoard>,
+  mut game_over: EventWriter<GameOver>,
+) {
+  // leaving mid-match quits both games
+  if versus.is_some_and(|v| !v.over) {
+    announce_results([Some(GameOverReason::Quit); 2], boards.iter(), &mut game_over);
+  }
+  for e in items.iter() {
+    commands.entity(e).despawn_recursive();
+  }
+  for mut visibility in single_player.iter_mut() {
+    *visibility = Visibility::Inherited;
+  }
+  for e in cup_colliders.iter() {
+    commands.entity(e).remove::<ColliderDisabled>();
+  }
+  commands.remove_resource::<VersusMatch>();
+  rapier_config.physics_pipeline_active = false;
+}
+
+// --- HELPER FUNCTIONS ---
+// a GameOver for each player whose game ended
+fn announce_results<'a>(
+  reasons: [Option<GameOverReason>; 2],
+  boards: impl Iterator<Item = &'a VersusBoard>,
+  game_over: &mut EventWriter<GameOver>,
+) {
+  for board in boards {
+    if let Some(reason) = reasons[board.player] {
+      game_over.send(GameOver { reason, score: board.score, player: Some(board.player), stats: board.stats });
+    }
+  }
+}
+
+// small fruits sent to the other player for a merge into `tier`
+pub fn garbage_for(tier: i32) -> u32 {
+  (tier - GARBAGE_TIER + 1).max(0) as u32
+}
+
+fn board_offset(player: usize) -> f32 {
+  if player == 0 { -VERSUS_OFFSET } else { VERSUS_OFFSET }
+}
+
+// the single player cup, moved over to `player`'s side
+fn positions_for(positions: &Positions, player: usize) -> Positions {
+  Positions {
+    cup_base_y: positions.cup_base_y,
+    cup_max_y: positions.cup_max_y,
+    cup_left_x: positions.cup_left_x + board_offset(player),
+    cup_right_x: positions.cup_right_x + board_offset(player),
+  }
+}
+
+// fresh boards for both players, who get the same fruits. A fixed seed
+// (--seed) plays the same match every time
+fn start_match(commands: &mut Commands, positions: &Positions, seed: &GameSeed, settings: &Settings) {
+  let seed = seed.0.unwrap_or_else(|| GameRng::from_entropy().seed);
+  for player in 0..2 {
+    commands.spawn((
+      VersusItem,
+      VersusBoard {
+        player,
+        positions: positions_for(positions, player),
+        score: 0,
+        queue: FruitQueue::default(),
+        hold: HoldSlot::default(),
+        chain: Chain::default(),
+        stats: GameStats::default(),
+        incoming: 0,
+        wait: 0,
+        rng: GameRng::new(seed),
+        over_line: 0.0,
+      },
+      Controls::default(),
+      CoolDown { timer: Timer::new(Duration::from_secs_f32(settings.drop_cooldown), TimerMode::Once) },
+    ));
+  }
+  commands.insert_resource(VersusMatch::new(seed));
+}
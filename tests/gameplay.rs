@@ -0,0 +1,856 @@
+// merging, scoring and game over rules on a headless game, one update is one fixed tick
+use std::time::Duration;
+use bevy::{prelude::*, time::TimeUpdateStrategy, audio::GlobalVolume};
+use bevy_persistent::prelude::*;
+
+use suika_clone::{
+  sim::{headless_app, spawn_fruit, run_drops, fast_settings, board_snapshot},
+  ActiveFruit,
+  AppState,
+  AutoPlayer,
+  AutoPlayerPlugin,
+  Chain,
+  ChainLink,
+  Controls,
+  CurrentLevel,
+  DailyProgress,
+  DailyRun,
+  Fruit,
+  FruitDropped,
+  FruitQueue,
+  FruitMerged,
+  FruitTable,
+  GameMode,
+  GameOver,
+  GameOverReason,
+  GameSeed,
+  GameSet,
+  GameStats,
+  Goal,
+  HighScore,
+  HoldSlot,
+  Levels,
+  OnBoard,
+  PuzzlePlugin,
+  ResumeGame,
+  SavedGame,
+  SavePlugin,
+  Settings,
+  SettingsPlugin,
+  Positions,
+  Replay,
+  ReplayMode,
+  ReplayPlugin,
+  Score,
+  SuikaEnv,
+  VersusBoard,
+  VersusMatch,
+  VersusPlugin,
+};
+use suika_clone::util::{CONTAINER_T, FIXED_DT, TIME_ATTACK_SECONDS, ZEN_PENALTY, GARBAGE_TIER};
+use suika_clone::versus::garbage_for;
+
+// -- HARNESS --
+#[derive(Resource, Default)]
+struct Merges(Vec<FruitMerged>);
+
+fn collect_merges(mut merged: EventReader<FruitMerged>, mut merges: ResMut<Merges>) {
+  merges.0.extend(merged.iter().copied());
+}
+
+#[derive(Resource, Default)]
+struct GameOvers(Vec<GameOver>);
+
+fn collect_game_overs(mut game_over: EventReader<GameOver>, mut game_overs: ResMut<GameOvers>) {
+  game_overs.0.extend(game_over.iter().copied());
+}
+
+fn press_hold(mut controls: Query<&mut Controls>) {
+  for mut controls in controls.iter_mut() {
+    controls.hold = true;
+  }
+}
+
+// a running game with an empty cup and the first active fruit waiting
+fn new_game() -> App {
+  new_game_in(GameMode::Classic)
+}
+
+fn new_game_in(mode: GameMode) -> App {
+  let mut app = headless_app(Some(1));
+  app.insert_resource(mode)
+    .init_resource::<Merges>()
+    .add_systems(Update, collect_merges);
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  tick(&mut app, 2);
+  assert_eq!(state(&app), AppState::InGame);
+  app
+}
+
+fn tick(app: &mut App, ticks: usize) {
+  for _ in 0..ticks {
+    app.update();
+  }
+}
+
+fn state(app: &App) -> AppState {
+  app.world.resource::<State<AppState>>().get().clone()
+}
+
+fn tier(app: &App, id: i32) -> Fruit {
+  app.world.resource::<FruitTable>().get(id)
+}
+
+fn positions(app: &App) -> (f32, f32, f32) {
+  let positions = app.world.resource::<Positions>();
+  (positions.cup_base_y, positions.cup_max_y, positions.cup_right_x)
+}
+
+fn fruits_of_tier(app: &mut App, id: i32) -> Vec<Vec2> {
+  app.world.query::<(&Fruit, &Transform)>()
+    .iter(&app.world)
+    .filter(|(fruit, _)| fruit.id == id)
+    .map(|(_, transform)| transform.translation.truncate())
+    .collect()
+}
+
+// -- TESTS --
+#[test]
+fn same_tier_fruits_merge_at_their_midpoint() {
+  let mut app = new_game();
+  let (base_y, _, _) = positions(&app);
+  let n = 3;
+  let r = tier(&app, n).size / 2.0;
+  // side by side on the floor, just touching
+  let a = Vec2::new(-r + 0.5, base_y + r);
+  let b = Vec2::new(r - 0.5, base_y + r);
+  spawn_fruit(&mut app, n, a);
+  spawn_fruit(&mut app, n, b);
+  tick(&mut app, 10);
+
+  let merges = &app.world.resource::<Merges>().0;
+  assert_eq!(merges.len(), 1, "expected exactly one merge, got {:?}", merges);
+  let merge = merges[0];
+  assert_eq!(merge.input_id, n);
+  assert_eq!(merge.output_id, n + 1);
+  let midpoint = (a + b) / 2.0;
+  assert!(merge.position.distance(midpoint) < 2.0, "merged at {} not {}", merge.position, midpoint);
+  let score = tier(&app, n + 1).score;
+  assert_eq!(merge.score, score);
+  assert_eq!(app.world.resource::<Score>().0, score);
+
+  assert!(fruits_of_tier(&mut app, n).is_empty());
+  assert_eq!(fruits_of_tier(&mut app, n + 1).len(), 1);
+}
+
+#[test]
+fn merges_still_happen_with_several_frames_per_tick() {
+  let mut app = new_game();
+  // like a 144hz screen, a few frames go by between ticks
+  app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(FIXED_DT / 3.0)));
+  let (base_y, _, _) = positions(&app);
+  let n = 3;
+  let r = tier(&app, n).size / 2.0;
+  spawn_fruit(&mut app, n, Vec2::new(-r + 0.5, base_y + r));
+  spawn_fruit(&mut app, n, Vec2::new(r - 0.5, base_y + r));
+  tick(&mut app, 40);
+
+  assert_eq!(app.world.resource::<Merges>().0.len(), 1);
+  assert_eq!(fruits_of_tier(&mut app, n + 1).len(), 1);
+}
+
+#[test]
+fn pairs_touching_in_the_same_tick_all_merge() {
+  let mut app = new_game();
+  let (base_y, _, right_x) = positions(&app);
+  let n = 1;
+  let r = tier(&app, n).size / 2.0;
+  // two pairs at opposite ends of the cup, both touching from the first step
+  for x in [-right_x / 2.0, right_x / 2.0] {
+    spawn_fruit(&mut app, n, Vec2::new(x - r + 0.5, base_y + r));
+    spawn_fruit(&mut app, n, Vec2::new(x + r - 0.5, base_y + r));
+  }
+  tick(&mut app, 5);
+
+  assert_eq!(app.world.resource::<Merges>().0.len(), 2);
+  assert!(fruits_of_tier(&mut app, n).is_empty());
+  assert_eq!(fruits_of_tier(&mut app, n + 1).len(), 2);
+}
+
+#[test]
+fn cascade_scores_each_merge_times_the_chain_length() {
+  let mut app = new_game();
+  let (base_y, _, right_x) = positions(&app);
+  let n = 1;
+  let r = |id: i32| tier(&app, id).size / 2.0;
+  let (r0, r1, r2) = (r(n), r(n + 1), r(n + 2));
+  // a touching pair, and a fruit of each of the next tiers falling onto its product
+  spawn_fruit(&mut app, n, Vec2::new(-r0 + 0.5, base_y + r0));
+  spawn_fruit(&mut app, n, Vec2::new(r0 - 0.5, base_y + r0));
+  let y1 = base_y + 2.0 * r0 + r1 + 20.0;
+  spawn_fruit(&mut app, n + 1, Vec2::new(0.0, y1));
+  spawn_fruit(&mut app, n + 2, Vec2::new(0.0, y1 + r1 + r2 + 20.0));
+  tick(&mut app, 120);
+
+  let merges = app.world.resource::<Merges>().0.iter().map(|m| (m.output_id, m.score)).collect::<Vec<_>>();
+  let expected = (1..=3).map(|len| (n + len, tier(&app, n + len).score * len)).collect::<Vec<_>>();
+  assert_eq!(merges, expected);
+  assert_eq!(app.world.resource::<Score>().0, expected.iter().map(|(_, score)| score).sum::<i32>());
+  let chain = *app.world.resource::<Chain>();
+  assert_eq!((chain.count, chain.longest), (3, 3));
+
+  // once the window is over the next merge starts a new chain at x1
+  tick(&mut app, 60 * 4);
+  let x = right_x / 2.0;
+  spawn_fruit(&mut app, n, Vec2::new(x - r0 + 0.5, base_y + r0));
+  spawn_fruit(&mut app, n, Vec2::new(x + r0 - 0.5, base_y + r0));
+  tick(&mut app, 5);
+  let last = *app.world.resource::<Merges>().0.last().unwrap();
+  assert_eq!(last.score, tier(&app, n + 1).score);
+  let next = *app.world.resource::<Chain>();
+  assert_eq!((next.id, next.count, next.longest), (chain.id + 1, 1, 3));
+}
+
+#[test]
+fn largest_fruits_do_not_merge() {
+  let mut app = new_game();
+  let (base_y, _, _) = positions(&app);
+  let top = app.world.resource::<FruitTable>().top_id();
+  let size = tier(&app, top).size;
+  // stacked, the cup is too narrow for two side by side
+  spawn_fruit(&mut app, top, Vec2::new(0.0, base_y + size / 2.0));
+  spawn_fruit(&mut app, top, Vec2::new(0.0, base_y + size * 1.5));
+  tick(&mut app, 60);
+
+  assert!(app.world.resource::<Merges>().0.is_empty());
+  assert_eq!(fruits_of_tier(&mut app, top).len(), 2);
+  assert_eq!(app.world.resource::<Score>().0, 0);
+}
+
+#[test]
+fn fruit_tables_need_a_tier_past_the_ones_that_spawn() {
+  let table = FruitTable::load();
+  assert_eq!(table.len(), FruitTable::builtin().len());
+  assert!(table.largest_spawn_id() < table.top_id());
+
+  let tiers = "(spawn_tiers: 2, tiers: [(size: 30.0, score: 0, color: (1.0, 1.0, 1.0)), (size: 40.0, score: 1, color: (1.0, 1.0, 1.0))])";
+  assert!(FruitTable::from_ron(tiers).is_err());
+  assert!(FruitTable::from_ron(&tiers.replace("spawn_tiers: 2", "spawn_tiers: 1")).is_ok());
+}
+
+#[test]
+fn fruit_resting_above_the_line_ends_the_game_after_the_timeout() {
+  let mut app = new_game();
+  let (_, max_y, _) = positions(&app);
+  let e = spawn_fruit(&mut app, 0, Vec2::new(0.0, max_y + 40.0));
+  // keep it up there without moving
+  app.world.entity_mut(e).insert(bevy_rapier2d::prelude::RigidBody::Fixed);
+
+  // the timeout is half a second
+  tick(&mut app, 15);
+  assert_eq!(state(&app), AppState::InGame);
+  assert!(app.world.get_resource::<GameOverReason>().is_none());
+
+  tick(&mut app, 30);
+  assert_eq!(state(&app), AppState::GameOver);
+  match app.world.get_resource::<GameOverReason>() {
+    Some(GameOverReason::MaxHeight(offender)) => assert_eq!(offender.entity, e),
+    other => panic!("expected MaxHeight, got {:?}", other),
+  }
+}
+
+#[test]
+fn fruit_past_the_right_wall_ends_the_game_at_once() {
+  let mut app = new_game();
+  let (base_y, _, right_x) = positions(&app);
+  let e = spawn_fruit(&mut app, 0, Vec2::new(right_x + CONTAINER_T + 30.0, base_y + 100.0));
+
+  // one tick to notice, one for the state change
+  tick(&mut app, 1);
+  match app.world.get_resource::<GameOverReason>() {
+    Some(GameOverReason::RightBoundary(offender)) => assert_eq!(offender.entity, e),
+    other => panic!("expected RightBoundary, got {:?}", other),
+  }
+  tick(&mut app, 1);
+  assert_eq!(state(&app), AppState::GameOver);
+}
+
+#[test]
+fn time_attack_ends_when_the_clock_runs_out() {
+  let mut app = new_game_in(GameMode::TimeAttack);
+  // skip ahead to the last few ticks
+  app.world.resource_mut::<GameStats>().duration = TIME_ATTACK_SECONDS - 0.05;
+  tick(&mut app, 1);
+  assert_eq!(state(&app), AppState::InGame);
+
+  tick(&mut app, 10);
+  assert_eq!(state(&app), AppState::GameOver);
+  assert!(matches!(app.world.get_resource::<GameOverReason>(), Some(GameOverReason::TimeUp)));
+}
+
+#[test]
+fn classic_games_have_no_time_limit() {
+  let mut app = new_game();
+  app.world.resource_mut::<GameStats>().duration = TIME_ATTACK_SECONDS + 10.0;
+  tick(&mut app, 5);
+  assert_eq!(state(&app), AppState::InGame);
+}
+
+#[test]
+fn zen_removes_overflowing_fruits_for_a_penalty() {
+  let mut app = new_game_in(GameMode::Zen);
+  let (base_y, max_y, right_x) = positions(&app);
+  app.world.resource_mut::<Score>().0 = 50;
+  // knocked over the wall once it's in play
+  let out = spawn_fruit(&mut app, 0, Vec2::new(0.0, base_y + 100.0));
+  tick(&mut app, 1);
+  app.world.get_mut::<Transform>(out).unwrap().translation.x = right_x + CONTAINER_T + 30.0;
+  let e = spawn_fruit(&mut app, 1, Vec2::new(0.0, max_y + 40.0));
+  app.world.entity_mut(e).insert(bevy_rapier2d::prelude::RigidBody::Fixed);
+
+  tick(&mut app, 60);
+  assert_eq!(state(&app), AppState::InGame);
+  assert!(app.world.get_resource::<GameOverReason>().is_none());
+  assert!(fruits_of_tier(&mut app, 0).is_empty());
+  assert!(fruits_of_tier(&mut app, 1).is_empty());
+  assert_eq!(app.world.resource::<Score>().0, 50 - 2 * ZEN_PENALTY);
+
+  // and the session best stays the classic one
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::GameOver);
+  tick(&mut app, 1);
+  assert_eq!(app.world.resource::<Score>().1, 0);
+}
+
+// the fruits coming up at the start of a daily challenge
+fn daily_queue(day: i64, seed: Option<u64>) -> Vec<i32> {
+  let mut app = headless_app(seed);
+  app.insert_resource(GameMode::Daily)
+    .insert_resource(DailyRun { day, practice: false });
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  tick(&mut app, 2);
+  app.world.resource::<FruitQueue>().fruits.iter().copied().collect()
+}
+
+#[test]
+fn daily_challenge_fruits_depend_only_on_the_day() {
+  let day = 739_000;
+  assert_eq!(daily_queue(day, None), daily_queue(day, None));
+  // a few days in a row, not all the same
+  let days: Vec<_> = (day..day + 4).map(|d| daily_queue(d, None)).collect();
+  assert!(days.iter().any(|queue| *queue != days[0]));
+  // a replay plays the seed it was recorded with, whatever the day
+  assert_eq!(daily_queue(day, Some(3)), daily_queue(day + 1, Some(3)));
+  assert_ne!(daily_queue(day, Some(3)), daily_queue(day, None));
+}
+
+#[test]
+fn daily_attempts_count_once_a_day_and_build_a_streak() {
+  let mut progress = DailyProgress::default();
+  assert!(progress.attempt(100));
+  assert!(!progress.attempt(100), "second game of the day is practice");
+  assert!(progress.attempt(101));
+  assert!(progress.attempt(102));
+  assert_eq!(progress.current_streak(102), 3);
+  assert_eq!(progress.current_streak(103), 3, "still alive until the day is missed");
+  assert_eq!(progress.current_streak(104), 0);
+
+  assert!(progress.attempt(105));
+  assert_eq!(progress.streak, 1);
+  assert_eq!(progress.best_streak, 3);
+  assert_eq!(progress.days_played, 4);
+}
+
+#[test]
+fn shipped_levels_load_and_fit_the_cup() {
+  let levels = Levels::load();
+  // every file in assets/levels is built in, and none of them got dropped
+  let files = std::fs::read_dir("assets/levels").unwrap()
+    .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "ron"))
+    .count();
+  assert_eq!(Levels::builtin().0.len(), files);
+  assert_eq!(levels.0.len(), files);
+  let app = new_game();
+  let (_, max_y, right_x) = positions(&app);
+  for level in levels.0.iter() {
+    assert!(!level.drops.is_empty(), "{} has no drops", level.id);
+    for fruit in level.fruits.iter() {
+      let r = tier(&app, fruit.id).size / 2.0;
+      assert!(fruit.x.abs() + r <= right_x, "{}: #{} sticks out of the cup", level.id, fruit.id);
+      assert!(fruit.y >= r - 1.0 && fruit.y + r < max_y, "{}: #{} below the floor or over the line", level.id, fruit.id);
+    }
+  }
+}
+
+#[test]
+fn puzzle_level_is_solved_by_reaching_its_goal() {
+  let mut app = headless_app(Some(1));
+  app.add_plugins(PuzzlePlugin)
+    .insert_resource(fast_settings())
+    .insert_resource(GameMode::Puzzle)
+    .insert_resource(CurrentLevel(0));
+  let level = app.world.resource::<Levels>().0[0].clone();
+  assert_eq!(level.goal, Goal::Create { tier: 4, drops: 3 });
+
+  // the first drop is a #3, right onto the #3 in the cup
+  let target = level.fruits.iter().find(|f| f.id == 3).unwrap().x;
+  let result = run_drops(&mut app, &[target, target, target]);
+  assert_eq!(result.reason, Some(GameOverReason::LevelComplete));
+  assert_eq!(state(&app), AppState::GameOver);
+}
+
+#[test]
+fn puzzle_level_fails_once_the_drops_run_out() {
+  let mut app = headless_app(Some(1));
+  app.add_plugins(PuzzlePlugin)
+    .insert_resource(fast_settings())
+    .insert_resource(GameMode::Puzzle)
+    .insert_resource(CurrentLevel(0));
+  // far from the #3, nothing merges into a #4
+  run_drops(&mut app, &[150.0, -150.0, 150.0]);
+  // once the board has been still for a moment
+  tick(&mut app, 60);
+  assert_eq!(state(&app), AppState::GameOver);
+  assert_eq!(app.world.get_resource::<GameOverReason>(), Some(&GameOverReason::OutOfDrops));
+}
+
+#[test]
+fn puzzle_hands_out_the_held_fruit_last() {
+  let mut app = headless_app(Some(1));
+  app.add_plugins(PuzzlePlugin)
+    .insert_resource(fast_settings())
+    .insert_resource(GameMode::Puzzle)
+    .insert_resource(CurrentLevel(0));
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  tick(&mut app, 2);
+  // put the first #3 aside
+  app.add_systems(FixedUpdate, press_hold.in_set(GameSet::Control).run_if(run_once()));
+  tick(&mut app, 1);
+  assert_eq!(app.world.resource::<HoldSlot>().fruit, Some(3));
+
+  let result = run_drops(&mut app, &[150.0, -150.0, 150.0, -150.0]);
+  assert_eq!(result.drops, 3);
+  assert_eq!(app.world.resource::<GameStats>().drops, 3);
+  assert_eq!(app.world.resource::<HoldSlot>().fruit, None);
+}
+
+// hold is pressed every tick while this is around
+#[derive(Resource)]
+struct Holding;
+
+fn active_id(app: &mut App) -> Option<i32> {
+  app.world.query::<&ActiveFruit>().iter(&app.world).next().map(|a| a.0)
+}
+
+#[test]
+fn hold_swaps_once_per_drop_and_clears_on_a_new_game() {
+  let mut app = new_game();
+  app.insert_resource(fast_settings())
+    .add_systems(FixedUpdate, press_hold.in_set(GameSet::Control).run_if(resource_exists::<Holding>()));
+  let first = active_id(&mut app).unwrap();
+  let next = app.world.resource::<FruitQueue>().fruits.front().copied();
+  // a second swap would show, the two differ for this seed
+  assert_ne!(Some(first), next);
+
+  // the first press puts the active fruit aside and brings out the next one
+  app.insert_resource(Holding);
+  tick(&mut app, 1);
+  assert_eq!(app.world.resource::<HoldSlot>().fruit, Some(first));
+  assert_eq!(active_id(&mut app), next);
+  // a second one before dropping is refused
+  tick(&mut app, 1);
+  assert_eq!(app.world.resource::<HoldSlot>().fruit, Some(first));
+  assert_eq!(active_id(&mut app), next);
+  app.world.remove_resource::<Holding>();
+
+  // after a drop the held fruit comes back
+  run_drops(&mut app, &[0.0]);
+  assert!(!app.world.resource::<HoldSlot>().swapped);
+  let current = active_id(&mut app);
+  app.insert_resource(Holding);
+  tick(&mut app, 1);
+  app.world.remove_resource::<Holding>();
+  assert_eq!(active_id(&mut app), Some(first));
+  assert_eq!(app.world.resource::<HoldSlot>().fruit, current);
+
+  // a new game starts with an empty slot
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::GameOver);
+  tick(&mut app, 1);
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  tick(&mut app, 2);
+  let hold = app.world.resource::<HoldSlot>();
+  assert_eq!((hold.fruit, hold.swapped), (None, false));
+}
+
+#[test]
+fn volume_setting_sets_the_global_volume() {
+  let mut app = headless_app(Some(1));
+  app.add_plugins(SettingsPlugin)
+    .insert_resource(Settings { volume: 0.3, ..default() });
+  tick(&mut app, 1);
+  assert_eq!(app.world.resource::<GlobalVolume>().volume.get(), 0.3);
+}
+
+#[test]
+fn autoplay_only_restarts_modes_that_start_straight_into_a_game() {
+  for (mode, restarts) in [(GameMode::Classic, true), (GameMode::Puzzle, false), (GameMode::Versus, false)] {
+    let mut app = headless_app(Some(1));
+    app.add_plugins(AutoPlayerPlugin)
+      .insert_resource(AutoPlayer::new(true))
+      .insert_resource(mode)
+      // a second a frame, well past the restart delay
+      .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs(1)));
+    app.world.resource_mut::<NextState<AppState>>().set(AppState::Menu);
+    tick(&mut app, 6);
+    assert_eq!(state(&app) == AppState::InGame, restarts, "{:?}", mode);
+  }
+}
+
+// a game with SavePlugin writing to `path`
+fn game_saved_to(path: &std::path::Path) -> App {
+  let mut app = headless_app(Some(1));
+  app.add_plugins(SavePlugin)
+    .insert_resource(Persistent::<SavedGame>::builder()
+      .name("saved game")
+      .format(StorageFormat::Bincode)
+      .path(path)
+      .default(SavedGame::default())
+      .build()
+      .unwrap());
+  app
+}
+
+#[test]
+fn saved_game_continues_with_its_chain() {
+  let path = std::env::temp_dir().join(format!("suika-test-save-{}.bin", std::process::id()));
+  let mut app = game_saved_to(&path);
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  tick(&mut app, 2);
+  // a merge starts a chain, its product carries the link
+  let (base_y, _, _) = positions(&app);
+  let r = tier(&app, 2).size / 2.0;
+  spawn_fruit(&mut app, 2, Vec2::new(-r + 0.5, base_y + r));
+  spawn_fruit(&mut app, 2, Vec2::new(r - 0.5, base_y + r));
+  tick(&mut app, 10);
+  let saved = board_snapshot(&mut app);
+  assert_eq!(saved.chain.count, 1);
+  assert!(saved.fruits.iter().any(|f| f.id == 3 && f.chain_link == Some(saved.chain.id)));
+  app.world.resource_mut::<Persistent<SavedGame>>().set(saved.clone()).unwrap();
+
+  // continue it from the file in a fresh app
+  let mut app = game_saved_to(&path);
+  app.insert_resource(ResumeGame);
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  tick(&mut app, 1);
+  std::fs::remove_file(&path).ok();
+
+  let chain = *app.world.resource::<Chain>();
+  assert_eq!((chain.id, chain.count, chain.longest), (saved.chain.id, saved.chain.count, saved.chain.longest));
+  assert_eq!(app.world.resource::<Score>().0, saved.score);
+  let links = app.world.query::<(&Fruit, &ChainLink)>()
+    .iter(&app.world)
+    .map(|(fruit, link)| (fruit.id, link.0))
+    .collect::<Vec<_>>();
+  assert_eq!(links, vec![(3, saved.chain.id)]);
+  let restored = board_snapshot(&mut app);
+  assert_eq!(restored.fruits.len(), saved.fruits.len());
+  assert_eq!(restored.queue, saved.queue);
+  assert_eq!(restored.active_fruit.map(|(id, _)| id), saved.active_fruit.map(|(id, _)| id));
+}
+
+// what save.bin held before high scores had names
+#[derive(Resource, serde::Serialize, serde::Deserialize)]
+struct LegacyHighScore([i32; 8]);
+
+#[test]
+fn legacy_high_scores_are_migrated() {
+  let path = std::env::temp_dir().join(format!("suika-test-legacy-{}.bin", std::process::id()));
+  Persistent::<LegacyHighScore>::builder()
+    .name("legacy high scores")
+    .format(StorageFormat::Bincode)
+    .path(&path)
+    .default(LegacyHighScore([900, 500, 300, 0, 0, 0, 0, 0]))
+    .build()
+    .unwrap()
+    .persist()
+    .unwrap();
+  let bytes = std::fs::read(&path).unwrap();
+  std::fs::remove_file(&path).ok();
+
+  let migrated = HighScore::from_legacy(&bytes).unwrap();
+  let entries = migrated.entries.iter().map(|e| (e.score, e.name.as_str())).collect::<Vec<_>>();
+  assert_eq!(entries, vec![(900, "???"), (500, "???"), (300, "???")]);
+  assert_eq!(migrated.best(), 900);
+  // anything else in save.bin is left to the new format
+  assert!(HighScore::from_legacy(&bytes[..16]).is_none());
+}
+
+// main app on the versus screen, both boards share its world
+fn new_versus() -> App {
+  let mut app = headless_app(Some(1));
+  app.add_plugins(VersusPlugin)
+    .init_resource::<Merges>()
+    .init_resource::<GameOvers>()
+    .add_systems(Update, (collect_merges, collect_game_overs));
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::Versus);
+  tick(&mut app, 1);
+  assert_eq!(state(&app), AppState::Versus);
+  app
+}
+
+fn board(app: &mut App, player: usize) -> &VersusBoard {
+  app.world.query::<&VersusBoard>()
+    .iter(&app.world)
+    .find(|board| board.player == player)
+    .unwrap()
+}
+
+fn spawn_on_board(app: &mut App, player: usize, id: i32, position: Vec2) {
+  let e = spawn_fruit(app, id, position);
+  app.world.entity_mut(e).insert(OnBoard(player));
+}
+
+fn fruits_on_board(app: &mut App, player: usize, id: i32) -> Vec<Vec2> {
+  app.world.query::<(&Fruit, &Transform, &OnBoard)>()
+    .iter(&app.world)
+    .filter(|(fruit, _, on_board)| fruit.id == id && on_board.0 == player)
+    .map(|(_, transform, _)| transform.translation.truncate())
+    .collect()
+}
+
+fn active_x(app: &mut App, player: usize) -> f32 {
+  app.world.query::<(&ActiveFruit, &Transform, &OnBoard)>()
+    .iter(&app.world)
+    .find(|(_, _, on_board)| on_board.0 == player)
+    .map(|(_, transform, _)| transform.translation.x)
+    .unwrap()
+}
+
+// player 0 merges two fruits into a garbage tier + 1
+fn big_merge(app: &mut App) {
+  let positions = &board(app, 0).positions;
+  let (base_y, center_x) = (positions.cup_base_y, 0.5 * (positions.cup_left_x + positions.cup_right_x));
+  let n = GARBAGE_TIER;
+  let r = tier(app, n).size / 2.0;
+  spawn_on_board(app, 0, n, Vec2::new(center_x - r + 0.5, base_y + r));
+  spawn_on_board(app, 0, n, Vec2::new(center_x + r - 0.5, base_y + r));
+  tick(app, 60);
+}
+
+#[test]
+fn only_big_merges_send_garbage() {
+  assert_eq!(garbage_for(GARBAGE_TIER - 1), 0);
+  assert_eq!(garbage_for(GARBAGE_TIER), 1);
+  assert_eq!(garbage_for(GARBAGE_TIER + 2), 3);
+}
+
+#[test]
+fn big_merge_rains_garbage_on_the_other_cup() {
+  let mut app = new_versus();
+  big_merge(&mut app);
+
+  let n = GARBAGE_TIER;
+  assert_eq!(fruits_on_board(&mut app, 0, n + 1).len(), 1);
+  assert!(fruits_on_board(&mut app, 0, 0).is_empty(), "no garbage for the merging player");
+  // two #0s, unless they landed together and merged
+  let garbage = fruits_on_board(&mut app, 1, 0).len() + 2 * fruits_on_board(&mut app, 1, 1).len();
+  assert_eq!(garbage, garbage_for(n + 1) as usize);
+  assert_eq!(board(&mut app, 1).incoming, 0);
+  assert!(board(&mut app, 0).score > 0);
+  assert_eq!(board(&mut app, 1).score, 0);
+
+  // the same merge event as the single player game, tagged with the player
+  let score = board(&mut app, 0).score;
+  let merges = &app.world.resource::<Merges>().0;
+  let big = merges.iter().find(|m| m.output_id == n + 1).unwrap();
+  assert_eq!((big.player, big.score), (Some(0), score));
+  assert!(merges.iter().filter(|m| m.input_id == 0).all(|m| m.player == Some(1)));
+}
+
+#[test]
+fn garbage_falls_the_same_way_for_the_same_seed() {
+  let mut garbage = Vec::new();
+  for _ in 0..2 {
+    let mut app = new_versus();
+    big_merge(&mut app);
+    garbage.push(fruits_on_board(&mut app, 1, 0));
+  }
+  assert!(!garbage[0].is_empty());
+  assert_eq!(garbage[0], garbage[1]);
+}
+
+#[test]
+fn each_player_moves_their_own_fruit() {
+  let mut app = new_versus();
+  tick(&mut app, 1);
+  let start = [active_x(&mut app, 0), active_x(&mut app, 1)];
+  app.world.resource_mut::<Input<KeyCode>>().press(KeyCode::A);
+  tick(&mut app, 10);
+
+  assert!(active_x(&mut app, 0) < start[0]);
+  assert_eq!(active_x(&mut app, 1), start[1]);
+}
+
+#[test]
+fn first_player_to_overflow_loses() {
+  let mut app = new_versus();
+  let positions = &board(&mut app, 1).positions;
+  let (base_y, right_x) = (positions.cup_base_y, positions.cup_right_x);
+  spawn_on_board(&mut app, 1, 0, Vec2::new(right_x + CONTAINER_T + 30.0, base_y + 100.0));
+  tick(&mut app, 3);
+
+  let versus = app.world.resource::<VersusMatch>();
+  assert!(versus.over);
+  assert_eq!(versus.winner, Some(0));
+  assert_eq!(state(&app), AppState::Versus);
+
+  // both games end, with the reasons the single player game uses
+  let mut game_overs = app.world.resource::<GameOvers>().0.iter()
+    .map(|g| (g.player, g.reason.kind()))
+    .collect::<Vec<_>>();
+  game_overs.sort();
+  assert_eq!(game_overs, vec![(Some(0), "won"), (Some(1), "right wall")]);
+}
+
+#[test]
+fn env_reset_starts_over_in_the_same_app() {
+  let mut env = SuikaEnv::new();
+  let first = env.reset(3);
+  assert!(first.fruits.is_empty());
+  for x in [-60.0, 0.0, 60.0] {
+    env.step(x);
+  }
+  assert!(!env.observe().fruits.is_empty());
+
+  let again = env.reset(3);
+  assert_eq!(again.fruits, first.fruits);
+  assert_eq!((again.active, again.next, again.score), (first.active, first.next, 0));
+  assert!(!env.is_done());
+}
+
+// game that records to or plays back from the replay file at path
+fn replay_app(path: &std::path::Path, replay_mode: ReplayMode, seed: u64) -> App {
+  let mut app = headless_app(Some(seed));
+  app.add_plugins((ReplayPlugin, PuzzlePlugin))
+    .insert_resource(fast_settings())
+    .insert_resource(replay_mode)
+    .insert_resource(Persistent::<Replay>::builder()
+      .name("replay")
+      .format(StorageFormat::Bincode)
+      .path(path)
+      .default(Replay::default())
+      .build()
+      .unwrap());
+  app
+}
+
+#[test]
+fn replay_plays_back_a_puzzle_level_to_the_same_score() {
+  let path = std::env::temp_dir().join(format!("suika-test-replay-{}.bin", std::process::id()));
+  std::fs::remove_file(&path).ok();
+  let mut app = replay_app(&path, ReplayMode::Record, 9);
+  app.insert_resource(GameMode::Puzzle).insert_resource(CurrentLevel(1));
+  run_drops(&mut app, &[-150.0, -70.0, 20.0, 90.0, 150.0, 0.0]);
+  if state(&app) == AppState::InGame {
+    app.world.resource_mut::<NextState<AppState>>().set(AppState::GameOver);
+    tick(&mut app, 1);
+  }
+  let recorded = app.world.resource::<Score>().0;
+  assert!(recorded > 0);
+
+  // a fresh app set up for classic, the replay brings its own mode and level
+  let mut app = replay_app(&path, ReplayMode::Playback, 0);
+  let replay = app.world.resource::<Persistent<Replay>>();
+  let (seed, frames) = (replay.seed, replay.frames.len());
+  assert_eq!((replay.mode, replay.level.as_deref()), (GameMode::Puzzle, Some("02_spring_cleaning")));
+  app.insert_resource(GameSeed(Some(seed)));
+  app.world.resource_mut::<NextState<AppState>>().set(AppState::InGame);
+  // no time goes by in a fresh app's first update
+  tick(&mut app, frames + 1);
+  if state(&app) == AppState::InGame {
+    app.world.resource_mut::<NextState<AppState>>().set(AppState::GameOver);
+    tick(&mut app, 1);
+  }
+  std::fs::remove_file(&path).ok();
+
+  assert_eq!(*app.world.resource::<GameMode>(), GameMode::Puzzle);
+  assert_eq!(app.world.resource::<CurrentLevel>().0, 1);
+  assert_eq!(app.world.resource::<Score>().0, recorded);
+}
+
+// tier, position and spin of each fruit
+type Board = Vec<(i32, Vec2, f32)>;
+
+// what a scripted game looked like after a set number of ticks
+#[derive(Resource, Default)]
+struct Played {
+  ticks: usize,
+  at: usize,
+  // tier and depth of each dropped fruit, the depth sets its spin
+  dropped: Vec<(i32, f32)>,
+  board: Option<(Board, i32)>,
+}
+
+fn count_ticks(
+  mut played: ResMut<Played>,
+  fruits: Query<(&Fruit, &Transform, &bevy_rapier2d::prelude::Velocity)>,
+  score: Res<Score>,
+) {
+  played.ticks += 1;
+  if played.ticks == played.at {
+    let board = fruits.iter()
+      .map(|(fruit, transform, velocity)| (fruit.id, transform.translation.truncate(), velocity.angvel))
+      .collect();
+    played.board = Some((board, score.0));
+  }
+}
+
+fn collect_drops(
+  mut dropped: EventReader<FruitDropped>,
+  mut played: ResMut<Played>,
+  transforms: Query<&Transform>,
+) {
+  for drop in dropped.iter() {
+    let z = transforms.get(drop.entity).map_or(f32::NAN, |t| t.translation.z);
+    played.dropped.push((drop.id, z));
+  }
+}
+
+// drops at xs, `frame` seconds a frame, and takes the board 600 ticks in
+fn play(seed: u64, frame: f32, xs: &[f32]) -> Played {
+  let mut app = headless_app(Some(seed));
+  app.insert_resource(fast_settings())
+    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(frame)))
+    .insert_resource(Played { at: 600, ..default() })
+    // state after whole ticks
+    .add_systems(FixedUpdate, count_ticks.before(GameSet::Input).run_if(in_state(AppState::InGame)))
+    .add_systems(Update, collect_drops);
+  run_drops(&mut app, xs);
+  while app.world.resource::<Played>().board.is_none() {
+    app.update();
+  }
+  app.world.remove_resource::<Played>().unwrap()
+}
+
+const DROPS: [f32; 8] = [-120.0, -40.0, 40.0, 120.0, 0.0, -80.0, 80.0, 0.0];
+
+#[test]
+fn same_seed_drops_the_same_fruits_with_the_same_spins() {
+  let a = play(4, FIXED_DT, &DROPS);
+  let b = play(4, FIXED_DT, &DROPS);
+  assert_eq!(a.dropped.len(), DROPS.len());
+  assert_eq!(a.dropped, b.dropped);
+  assert_eq!(a.board, b.board);
+
+  let other = play(5, FIXED_DT, &DROPS);
+  assert_ne!(a.dropped, other.dropped);
+}
+
+#[test]
+fn frame_rate_does_not_change_the_game() {
+  let at_60 = play(4, FIXED_DT, &DROPS);
+  for frame in [1.0 / 144.0, 1.0 / 30.0] {
+    let other = play(4, frame, &DROPS);
+    assert_eq!(other.dropped, at_60.dropped);
+    assert_eq!(other.board, at_60.board);
+  }
+}